@@ -2,25 +2,107 @@ grammar Expr;
 
 program: stmt*;
 
-stmt: print_stmt | assignStmt | var_stmt;
+stmt: print_stmt | var_stmt | fn_decl | if_stmt | while_stmt | for_stmt | jump_stmt | return_stmt | block | expr_stmt;
 
 print_stmt: PRINT expr SEMICOLON;
 
-assignStmt: IDENTIFIER '=' expr SEMICOLON;
 
 var_stmt: VAR IDENTIFIER ('=' expr)? SEMICOLON;
 
-expr: addition;
+fn_decl: FN IDENTIFIER '(' (IDENTIFIER (',' IDENTIFIER)*)? ')' block;
 
+if_stmt: IF '(' expr ')' block (ELSE (block | if_stmt))?;
+
+while_stmt: WHILE '(' expr ')' block;
+
+// loop variable goes from first value up to (not including) second one,
+// without range it takes elements of list, keys of map in insertion order or characters of string
+for_stmt: FOR IDENTIFIER IN expr ('..' expr)? block;
+
+jump_stmt: (BREAK | CONTINUE) SEMICOLON;
+
+// returns 0 when value is omitted, as does falling off the end of function
+return_stmt: RETURN expr? SEMICOLON;
+
+block: '{' stmt* '}';
+
+// value is discarded. in REPL values of top level expression statements (except assignments) are printed
+expr_stmt: expr SEMICOLON;
+
+/*
+operator precedence, loosest first:
+
+= += -= *= /= %=        right
+or                      left
+and                     left
+not                     prefix
+== !=                   left
+< <= > >= in            left
+|                       left
+^                       left
+&                       left
+<< >>                   left
++ -                     left
+* / div %               left
+- + ~                   prefix
+**                      right, binds tighter than prefix operator on its left: -2**2 is -4
+call, index             postfix
+
+/ truncates integers toward zero, div and % round toward negative infinity (-7 div 2 is -4, -7 % 2 is 1).
+int ** negative int is float. bitwise operators accept integers only
+ */
+expr: assignment;
+
+// right-associative, value of assignment is the assigned value. a += b is a = a + b
+assignment: (IDENTIFIER | call '[' expr ']') ('=' | '+=' | '-=' | '*=' | '/=' | '%=') assignment | or_expr;
+
+or_expr: and_expr (OR and_expr)*;
+and_expr: negation (AND negation)*;
+negation: NOT negation | equality;
+equality: comparison (('=='|'!=') comparison)*;
+// x in m checks for key of map, element of list or substring
+comparison: bit_or (('<'|'<='|'>'|'>='|IN) bit_or)*;
+bit_or: bit_xor ('|' bit_xor)*;
+bit_xor: bit_and ('^' bit_and)*;
+bit_and: shift ('&' shift)*;
+shift: addition (('<<'|'>>') addition)*;
 addition: mult (('+'|'-') mult)*;
-mult: term (('*'|'/')term)*;
-term: NUMBER | IDENTIFIER | '(' expr ')';
+mult: unary (('*'|'/'|DIV|'%') unary)*;
+unary: ('-'|'+'|'~') unary | power;
+power: call ('**' unary)?;
+// len(x) is builtin length of list or string unless variable len is visible
+call: term ('(' (expr (',' expr)*)? ')' | '[' expr ']')*;
+term: NUMBER | FLOAT | BOOL | STRING | IDENTIFIER | lambda | list | map | '(' expr ')';
+// lists are shared by reference, indexes start at 0 and are bounds-checked
+list: '[' (expr (',' expr)*)? ']';
+// keys are ints or strings. '{' at start of statement is block, not map
+map: '{' (expr ':' expr (',' expr ':' expr)*)? '}';
+// function value, captures variables of enclosing functions
+lambda: FN '(' (IDENTIFIER (',' IDENTIFIER)*)? ')' block;
 
 WS: (' '| '\t'| '\n') -> channel(HIDDEN);
 
 NUMBER: [1-9][0-9]*;
+// digit is required after '.', so 1..10 is a range
+FLOAT: [0-9]+ ('.' [0-9]+ ([eE] [+-]? [0-9]+)? | [eE] [+-]? [0-9]+);
+BOOL: 'true' | 'false';
+// escapes: \n \t \r \0 \\ \"
+STRING: '"' ('\\' [ntr0\\"] | ~["\\\n])* '"';
 PRINT: 'print';
 SEMICOLON: ';';
 VAR: 'var';
+IF: 'if';
+ELSE: 'else';
+WHILE: 'while';
+FOR: 'for';
+IN: 'in';
+BREAK: 'break';
+CONTINUE: 'continue';
+FN: 'fn';
+RETURN: 'return';
+AND: 'and';
+OR: 'or';
+NOT: 'not';
+DIV: 'div';
 
 IDENTIFIER: [A-Za-z_][A-Za-z0-9_]*;
@@ -0,0 +1,507 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::ops::{Add, BitAnd, BitOr, BitXor, Mul, Neg, Sub};
+
+/*
+arbitrary precision integer used by VM in big integer mode.
+stored as sign and magnitude, magnitude is little-endian vector of 32-bit limbs
+without leading zero limbs (zero is empty vector and is never negative)
+ */
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    limbs: Vec<u32>
+}
+
+fn trim(mut limbs: Vec<u32>) -> Vec<u32> {
+    while let Some(0) = limbs.last() {
+        limbs.pop();
+    }
+    return limbs;
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    if a.len()!=b.len() {
+        return a.len().cmp(&b.len());
+    }
+    for (x, y) in a.iter().rev().zip(b.iter().rev()) {
+        if x!=y {
+            return x.cmp(y);
+        }
+    }
+    return Ordering::Equal;
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len()>=b.len() {(a, b)} else {(b, a)};
+    let mut res = Vec::with_capacity(long.len()+1);
+    let mut carry = 0u64;
+    for (i, x) in long.iter().enumerate() {
+        let sum = *x as u64 + *short.get(i).unwrap_or(&0) as u64 + carry;
+        res.push(sum as u32);
+        carry = sum>>32;
+    }
+    if carry!=0 {
+        res.push(carry as u32);
+    }
+    return res;
+}
+
+/// a - b, a is expected to be not less than b
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut res = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, x) in a.iter().enumerate() {
+        let mut diff = *x as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = 0;
+        if diff<0 {
+            diff += 1<<32;
+            borrow = 1;
+        }
+        res.push(diff as u32);
+    }
+    return trim(res);
+}
+
+fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    if a.is_empty() || b.is_empty() {
+        return Vec::new();
+    }
+    let mut res = vec![0u32; a.len()+b.len()];
+    for (i, x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, y) in b.iter().enumerate() {
+            let cur = res[i+j] as u64 + (*x as u64)*(*y as u64) + carry;
+            res[i+j] = cur as u32;
+            carry = cur>>32;
+        }
+        res[i+b.len()] = carry as u32;
+    }
+    return trim(res);
+}
+
+fn divmod_small(a: &[u32], d: u32) -> (Vec<u32>, u32) {
+    let mut quotient = vec![0u32; a.len()];
+    let mut rem = 0u64;
+    for i in (0..a.len()).rev() {
+        let cur = (rem<<32) | a[i] as u64;
+        quotient[i] = (cur/d as u64) as u32;
+        rem = cur%d as u64;
+    }
+    return (trim(quotient), rem as u32);
+}
+
+/// quotient and remainder of magnitudes, b is not zero
+fn divmod_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if cmp_mag(a, b)==Ordering::Less {
+        return (Vec::new(), a.to_vec());
+    }
+    if b.len()==1 {
+        let (quotient, rem) = divmod_small(a, b[0]);
+        return (quotient, trim(vec![rem]));
+    }
+
+    //binary long division, one bit of dividend at a time
+    let mut quotient = vec![0u32; a.len()];
+    let mut rem: Vec<u32> = Vec::new();
+    for bit in (0..a.len()*32).rev() {
+        let mut carry = (a[bit/32]>>(bit%32)) & 1;
+        for limb in rem.iter_mut() {
+            let next_carry = *limb>>31;
+            *limb = (*limb<<1) | carry;
+            carry = next_carry;
+        }
+        if carry!=0 {
+            rem.push(carry);
+        }
+
+        if cmp_mag(&rem, b)!=Ordering::Less {
+            rem = sub_mag(&rem, b);
+            quotient[bit/32] |= 1<<(bit%32);
+        }
+    }
+    return (trim(quotient), rem);
+}
+
+impl BigInt {
+    fn new(negative: bool, limbs: Vec<u32>) -> BigInt {
+        let limbs = trim(limbs);
+        let negative = negative && !limbs.is_empty();
+        BigInt{negative, limbs}
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    /// number of bits in magnitude, 0 for zero
+    pub fn bits(&self) -> u64 {
+        match self.limbs.last() {
+            Some(top) => {(self.limbs.len() as u64-1)*32 + (32-top.leading_zeros()) as u64}
+            None => {0}
+        }
+    }
+
+    /// approximate amount of memory taken by digits
+    pub fn size(&self) -> usize {
+        self.limbs.len()*std::mem::size_of::<u32>()
+    }
+
+    /// parses string of decimal digits without sign
+    pub fn from_decimal(digits: &str) -> Option<BigInt> {
+        if digits.is_empty() || !digits.bytes().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let mut limbs: Vec<u32> = Vec::new();
+        //nine digits at a time still fit into u32
+        for chunk in digits.as_bytes().chunks(9) {
+            let chunk = std::str::from_utf8(chunk).unwrap();
+            let multiplier = 10u32.pow(chunk.len() as u32);
+            let mut carry = chunk.parse::<u32>().unwrap() as u64;
+            for limb in limbs.iter_mut() {
+                let cur = (*limb as u64)*(multiplier as u64) + carry;
+                *limb = cur as u32;
+                carry = cur>>32;
+            }
+            if carry!=0 {
+                limbs.push(carry as u32);
+            }
+        }
+        return Some(BigInt::new(false, limbs));
+    }
+
+    pub fn to_i32(&self) -> Option<i32> {
+        if self.limbs.len()>1 {
+            return None;
+        }
+        let magnitude = *self.limbs.first().unwrap_or(&0) as i64;
+        return i32::try_from(if self.negative {-magnitude} else {magnitude}).ok();
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let magnitude = self.limbs.iter().rev().fold(0.0, |acc, limb| acc*4294967296.0 + *limb as f64);
+        return if self.negative {-magnitude} else {magnitude};
+    }
+
+    /// division rounding toward zero, as i32 does. None if other is zero
+    pub fn checked_div(&self, other: &BigInt) -> Option<BigInt> {
+        if other.is_zero() {
+            return None;
+        }
+        let (quotient, _) = divmod_mag(&self.limbs, &other.limbs);
+        return Some(BigInt::new(self.negative!=other.negative, quotient));
+    }
+
+    /// quotient rounded toward negative infinity and remainder with sign of other. None if other is zero
+    pub fn checked_divmod_floor(&self, other: &BigInt) -> Option<(BigInt, BigInt)> {
+        if other.is_zero() {
+            return None;
+        }
+        let (quotient, rem) = divmod_mag(&self.limbs, &other.limbs);
+        let quotient = BigInt::new(self.negative!=other.negative, quotient);
+        let rem = BigInt::new(self.negative, rem);
+        if !rem.is_zero() && rem.negative!=other.negative {
+            return Some((&quotient - &BigInt::from(1), &rem + other));
+        }
+        return Some((quotient, rem));
+    }
+
+    pub fn pow(&self, mut exponent: u32) -> BigInt {
+        //square and multiply
+        let mut res = BigInt::from(1);
+        let mut base = self.clone();
+        while exponent>0 {
+            if exponent & 1 == 1 {
+                res = &res * &base;
+            }
+            exponent >>= 1;
+            if exponent>0 {
+                base = &base * &base;
+            }
+        }
+        return res;
+    }
+
+    /// two's complement representation in len limbs, len must leave room for sign bit
+    fn to_twos_complement(&self, len: usize) -> Vec<u32> {
+        let mut limbs = if self.negative {sub_mag(&self.limbs, &[1])} else {self.limbs.clone()};
+        limbs.resize(len, 0);
+        if self.negative {
+            for limb in limbs.iter_mut() {
+                *limb = !*limb;
+            }
+        }
+        return limbs;
+    }
+
+    fn from_twos_complement(mut limbs: Vec<u32>) -> BigInt {
+        if limbs.last().is_some_and(|limb| limb>>31==1) {
+            for limb in limbs.iter_mut() {
+                *limb = !*limb;
+            }
+            return BigInt::new(true, add_mag(&limbs, &[1]));
+        }
+        return BigInt::new(false, limbs);
+    }
+
+    /// applies op to every limb of two's complement representations, as if numbers had infinite sign extension
+    fn bitwise(&self, other: &BigInt, op: fn(u32, u32) -> u32) -> BigInt {
+        let len = self.limbs.len().max(other.limbs.len())+1;
+        let a = self.to_twos_complement(len);
+        let b = other.to_twos_complement(len);
+        let limbs = a.iter().zip(b.iter()).map(|(x, y)| op(*x, *y)).collect();
+        return BigInt::from_twos_complement(limbs);
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(value: i64) -> Self {
+        let magnitude = value.unsigned_abs();
+        BigInt::new(value<0, vec![magnitude as u32, (magnitude>>32) as u32])
+    }
+}
+
+impl From<i32> for BigInt {
+    fn from(value: i32) -> Self {
+        BigInt::from(value as i64)
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => {Ordering::Greater}
+            (true, false) => {Ordering::Less}
+            (false, false) => {cmp_mag(&self.limbs, &other.limbs)}
+            (true, true) => {cmp_mag(&other.limbs, &self.limbs)}
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::new(!self.negative, self.limbs.clone())
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative==other.negative {
+            return BigInt::new(self.negative, add_mag(&self.limbs, &other.limbs));
+        }
+        //different signs, result has sign of operand with bigger magnitude
+        match cmp_mag(&self.limbs, &other.limbs) {
+            Ordering::Less => {BigInt::new(other.negative, sub_mag(&other.limbs, &self.limbs))}
+            _ => {BigInt::new(self.negative, sub_mag(&self.limbs, &other.limbs))}
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &(-other)
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        BigInt::new(self.negative!=other.negative, mul_mag(&self.limbs, &other.limbs))
+    }
+}
+
+impl BitAnd for &BigInt {
+    type Output = BigInt;
+
+    fn bitand(self, other: &BigInt) -> BigInt {
+        self.bitwise(other, |x, y| x & y)
+    }
+}
+
+impl BitOr for &BigInt {
+    type Output = BigInt;
+
+    fn bitor(self, other: &BigInt) -> BigInt {
+        self.bitwise(other, |x, y| x | y)
+    }
+}
+
+impl BitXor for &BigInt {
+    type Output = BigInt;
+
+    fn bitxor(self, other: &BigInt) -> BigInt {
+        self.bitwise(other, |x, y| x ^ y)
+    }
+}
+
+impl Display for BigInt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        //split into base 10^9 chunks, least significant first
+        let mut chunks = Vec::new();
+        let mut magnitude = self.limbs.clone();
+        while !magnitude.is_empty() {
+            let (quotient, rem) = divmod_small(&magnitude, 1_000_000_000);
+            chunks.push(rem);
+            magnitude = quotient;
+        }
+
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", chunks.last().unwrap())?;
+        for chunk in chunks.iter().rev().skip(1) {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::Value;
+
+    fn big(value: i128) -> BigInt {
+        let magnitude = BigInt::from_decimal(&value.unsigned_abs().to_string()).unwrap();
+        if value<0 {-&magnitude} else {magnitude}
+    }
+
+    /// operands around sign changes and limb boundaries
+    const SAMPLES: [i128; 16] = [
+        0, 1, -1, 2, -2, 7, -7,
+        i32::MAX as i128, i32::MIN as i128, u32::MAX as i128, 1<<32, -(1<<32),
+        i64::MAX as i128, i64::MIN as i128,
+        123456789012345678901234567890, -98765432109876543210987654321
+    ];
+
+    fn floor_divmod(a: i128, b: i128) -> (i128, i128) {
+        let mut quotient = a/b;
+        if a%b!=0 && (a<0)!=(b<0) {
+            quotient -= 1;
+        }
+        (quotient, a-quotient*b)
+    }
+
+    #[test]
+    fn decimal_round_trip() {
+        for a in SAMPLES {
+            assert_eq!(big(a).to_string(), a.to_string());
+        }
+        assert_eq!(BigInt::from_decimal("000123").unwrap().to_string(), "123");
+        assert!(BigInt::from_decimal("").is_none());
+        assert!(BigInt::from_decimal("-1").is_none());
+        assert!(BigInt::from_decimal("12a").is_none());
+    }
+
+    #[test]
+    fn zero_is_never_negative() {
+        let zero = &big(5) - &big(5);
+        assert!(!zero.is_negative());
+        assert_eq!(zero, big(0));
+        assert!(!(-&zero).is_negative());
+        assert_eq!((&big(-3) * &big(0)).to_string(), "0");
+    }
+
+    #[test]
+    fn arithmetic_matches_i128() {
+        for a in SAMPLES {
+            for b in SAMPLES {
+                assert_eq!((&big(a) + &big(b)).to_string(), (a+b).to_string(), "{} + {}", a, b);
+                assert_eq!((&big(a) - &big(b)).to_string(), (a-b).to_string(), "{} - {}", a, b);
+                if let Some(product) = a.checked_mul(b) {
+                    assert_eq!((&big(a) * &big(b)).to_string(), product.to_string(), "{} * {}", a, b);
+                }
+                assert_eq!(big(a).cmp(&big(b)), a.cmp(&b), "{} cmp {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn division_matches_i128() {
+        for a in SAMPLES {
+            for b in SAMPLES {
+                if b==0 {
+                    continue;
+                }
+                assert_eq!(big(a).checked_div(&big(b)).unwrap().to_string(), (a/b).to_string(), "{} / {}", a, b);
+                let (quotient, rem) = big(a).checked_divmod_floor(&big(b)).unwrap();
+                let (expected_quotient, expected_rem) = floor_divmod(a, b);
+                assert_eq!(quotient.to_string(), expected_quotient.to_string(), "{} div {}", a, b);
+                assert_eq!(rem.to_string(), expected_rem.to_string(), "{} % {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn division_by_zero() {
+        for a in SAMPLES {
+            assert!(big(a).checked_div(&big(0)).is_none());
+            assert!(big(a).checked_divmod_floor(&big(0)).is_none());
+        }
+    }
+
+    #[test]
+    fn bitwise_matches_i128() {
+        for a in SAMPLES {
+            for b in SAMPLES {
+                assert_eq!((&big(a) & &big(b)).to_string(), (a & b).to_string(), "{} & {}", a, b);
+                assert_eq!((&big(a) | &big(b)).to_string(), (a | b).to_string(), "{} | {}", a, b);
+                assert_eq!((&big(a) ^ &big(b)).to_string(), (a ^ b).to_string(), "{} ^ {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn pow_and_bits() {
+        assert_eq!(big(2).pow(100).to_string(), "1267650600228229401496703205376");
+        assert_eq!(big(-3).pow(3).to_string(), "-27");
+        assert_eq!(big(-3).pow(0).to_string(), "1");
+        assert_eq!(big(0).pow(5).to_string(), "0");
+        assert_eq!(big(0).bits(), 0);
+        assert_eq!(big(-1).bits(), 1);
+        assert_eq!(big(1<<32).bits(), 33);
+        assert_eq!(big(2).pow(100).bits(), 101);
+    }
+
+    #[test]
+    fn to_i32_boundary() {
+        assert_eq!(big(i32::MAX as i128).to_i32(), Some(i32::MAX));
+        assert_eq!(big(i32::MIN as i128).to_i32(), Some(i32::MIN));
+        assert_eq!(big(i32::MAX as i128+1).to_i32(), None);
+        assert_eq!(big(i32::MIN as i128-1).to_i32(), None);
+        assert_eq!(big(u32::MAX as i128).to_i32(), None);
+        assert_eq!(big(1<<32).to_i32(), None);
+        assert_eq!(big(0).to_i32(), Some(0));
+    }
+
+    #[test]
+    fn from_big_keeps_small_values_as_int() {
+        assert!(matches!(Value::from(big(i32::MAX as i128)), Value::Int(i32::MAX)));
+        assert!(matches!(Value::from(big(i32::MIN as i128)), Value::Int(i32::MIN)));
+        assert!(matches!(Value::from(big(i32::MAX as i128+1)), Value::BigInt(_)));
+        assert!(matches!(Value::from(big(i32::MIN as i128-1)), Value::BigInt(_)));
+    }
+}
@@ -1,57 +1,182 @@
 use crate::vm::OpCode;
 use crate::parser::{Expr, ExprType};
 use std::collections::{HashMap};
+use crate::error::{CompileError, CompileErrorKind};
+use crate::lexer::Span;
+use std::rc::Rc;
+use crate::bigint::BigInt;
+use std::io::{self, Write};
 
 pub struct Chunk{
     pub program:Vec<OpCode>,
     pub variable_size:usize,
 
-    pub constant_pool: Vec<i32>
+    pub constant_pool: Vec<Constant>,
+
+    /// source span of every instruction, spans[i] belongs to program[i]
+    pub spans: Vec<Span>
 }
 
 impl Chunk{
     pub fn new() -> Chunk{
-        return Chunk{program:Vec::new(), variable_size:0, constant_pool:Vec::new()};
+        return Chunk{program:Vec::new(), variable_size:0, constant_pool:Vec::new(), spans:Vec::new()};
+    }
+
+    /// appends instruction produced by source region span
+    pub fn write(&mut self, op:OpCode, span:Span) {
+        self.program.push(op);
+        self.spans.push(span);
     }
 
-    pub fn dump_stdout(&self){
-        println!("constant_size={}\nvariable_size={}", self.constant_pool.len(), self.variable_size);
-        for item in &self.program {
-            println!("{}", item);
+    /// writes instructions with positions in source, then chunks of functions
+    pub fn dump(&self, out:&mut dyn Write) -> io::Result<()> {
+        writeln!(out, "constant_size={}\nvariable_size={}", self.constant_pool.len(), self.variable_size)?;
+        for (item, span) in self.program.iter().zip(&self.spans) {
+            writeln!(out, "{:<16}{}", item.to_string(), span.start)?;
         }
+        for (idx, constant) in self.constant_pool.iter().enumerate() {
+            if let Constant::Function(function) = constant {
+                writeln!(out, "function {} ({}):", idx, function.name)?;
+                function.chunk.dump(out)?;
+            }
+        }
+        return Ok(());
     }
 
-    pub fn compile_from(ast:&Expr) -> Result<Chunk, String> {
+    pub fn compile_from(ast:&Expr) -> Result<Chunk, CompileError> {
         Compiler::compile(ast)
     }
 }
 
+impl Default for Chunk {
+    fn default() -> Self {
+        Chunk::new()
+    }
+}
+
+/// entry of chunk's constant pool
+pub enum Constant {
+    Int(i32),
+    BigInt(Rc<BigInt>),
+    Float(f64),
+    Str(Rc<str>),
+    /// prototype of closure, used only by Closure opcode
+    Function(Rc<Function>)
+}
+
+/// compiled user function, turned into closure by Closure opcode
+pub struct Function {
+    pub name:String,
+    pub arity:usize,
+    pub chunk:Chunk,
+    /// variables of enclosing functions used by this one, in order of upvalue indices
+    pub captures:Vec<Capture>
+}
+
+/// where closure takes its upvalue from when it is created
+#[derive(Copy, Clone, PartialEq)]
+pub struct Capture {
+    /// local slot of enclosing function if is_local, upvalue index of enclosing closure otherwise
+    pub index:usize,
+    pub is_local:bool
+}
+
+/// jumps of break and continue statements of loop being compiled, patched when loop ends
+struct LoopJumps {
+    breaks:Vec<usize>,
+    continues:Vec<usize>,
+    /// amount of locals that were alive when loop body started, jumps drop the rest
+    local_count:usize
+}
+
+/// variable declared inside a block, it lives on VM stack until block ends
+struct Local {
+    name:String,
+    depth:usize,
+    /// used by nested function, upvalue must be closed when local goes out of scope
+    captured:bool
+}
+
+#[derive(Copy, Clone)]
+enum Variable {
+    /// index in VM variable area
+    Global(usize),
+    /// index on stack relative to frame base
+    Local(usize),
+    /// index in upvalues of current closure
+    Upvalue(usize)
+}
+
+/// part of compiler state that belongs to function being compiled,
+/// state of enclosing function is put aside while nested one is compiled
+struct FunctionState {
+    locals:Vec<Local>,
+    scope_depth:usize,
+    loops:Vec<LoopJumps>,
+    captures:Vec<Capture>
+}
+
 pub struct Compiler {
-    name_map:HashMap<String, usize>
+    /// top-level variables, kept between continue_compile calls
+    name_map:HashMap<String, usize>,
+    /// amount of global variable slots allocated so far
+    variable_count:usize,
+    /// locals of enclosing blocks in order of declaration
+    locals:Vec<Local>,
+    /// amount of blocks around code being compiled, 0 for top level
+    scope_depth:usize,
+    /// enclosing loops, innermost last
+    loops:Vec<LoopJumps>,
+    /// variables captured by function being compiled
+    captures:Vec<Capture>,
+    /// states of functions around the one being compiled, empty for top level code
+    enclosing:Vec<FunctionState>,
+    /// native functions of host program by name: index in VM and arity
+    natives:HashMap<String, (usize, usize)>
 }
 
 impl Compiler {
 
     pub fn new() -> Compiler {
-        Compiler{name_map:HashMap::new()}
+        Compiler{name_map:HashMap::new(), variable_count:0, locals:Vec::new(), scope_depth:0, loops:Vec::new(), captures:Vec::new(), enclosing:Vec::new(), natives:HashMap::new()}
+    }
+
+    /// slot of top-level variable in VM
+    pub fn global_index(&self, name:&str) -> Option<usize> {
+        return self.name_map.get(name).copied();
+    }
+
+    /// declares top-level variable unless it exists, so that host program can set it before code using it is compiled.
+    /// returns its slot
+    pub fn define_global(&mut self, name:&str) -> usize {
+        if let Some(idx) = self.name_map.get(name) {
+            return *idx;
+        }
+        let idx = self.variable_count;
+        self.variable_count += 1;
+        self.name_map.insert(name.to_string(), idx);
+        return idx;
     }
 
-    pub fn compile(ast:&Expr) -> Result<Chunk, String> {
+    /// makes native function registered in VM under given index callable by name.
+    /// variables with same name shadow it
+    pub fn declare_native(&mut self, name:&str, index:usize, arity:usize) {
+        self.natives.insert(name.to_string(), (index, arity));
+    }
 
-        let mut name_map:HashMap<String, usize> = HashMap::new(); //variable -> variable_idx
-        Compiler::find_variables(ast, &mut name_map)?;
+    pub fn compile(ast:&Expr) -> Result<Chunk, CompileError> {
 
+        let mut comp = Compiler::new();
         let mut code_chunk = Chunk::new();
-        code_chunk.variable_size = name_map.len();
-
-        let mut comp = Compiler{ name_map };
         comp.compile_ast(&mut code_chunk, ast)?;
+        code_chunk.variable_size = comp.variable_count;
 
         Ok(code_chunk)
     }
 
-    pub fn continue_compile(&mut self, ast:&Expr) -> Result<Chunk, String> {
+    pub fn continue_compile(&mut self, ast:&Expr) -> Result<Chunk, CompileError> {
         let name_map_copy = self.name_map.clone();
+        let variable_count_copy = self.variable_count;
         //we don't want bad input to spoil compiler state
         //make copy to recover if needed
 
@@ -63,6 +188,12 @@ impl Compiler {
             Err(r) => {
                 //recover
                 self.name_map = name_map_copy;
+                self.variable_count = variable_count_copy;
+                self.locals.clear();
+                self.scope_depth = 0;
+                self.loops.clear();
+                self.captures.clear();
+                self.enclosing.clear();
                 Err(r)
             }
         }
@@ -70,65 +201,104 @@ impl Compiler {
     }
 
 
-    fn _continue_compile(&mut self, ast:&Expr) -> Result<Chunk, String> {
+    fn _continue_compile(&mut self, ast:&Expr) -> Result<Chunk, CompileError> {
         //has name_map
 
         //vm expects chunk.variable_size = amount of NEW variables added
         //but instructions like LOAD_VAR may use previously defined variables with corresponding indices
-        let initial_name_count = self.name_map.len();
+        let initial_variable_count = self.variable_count;
 
-        Compiler::find_variables(ast, &mut self.name_map)?;
+        let mut code_chunk = Chunk::new();
+        self.compile_ast(&mut code_chunk, ast)?;
 
         //because of that, we need to keep indexation, but change amount of variables to account
         //only for those that were introduced in current chunk
-        let mut code_chunk = Chunk::new();
-        code_chunk.variable_size = self.name_map.len() - initial_name_count;
-        self.compile_ast(&mut code_chunk, ast)?;
+        code_chunk.variable_size = self.variable_count - initial_variable_count;
 
         Ok(code_chunk)
     }
 
+    /// finds variable, innermost declaration wins
+    fn resolve_variable(&mut self, name:&str) -> Option<Variable> {
+        if let Some(idx) = self.locals.iter().rposition(|local| local.name==name) {
+            return Some(Variable::Local(idx));
+        }
+        if let Some(idx) = self.resolve_upvalue(name) {
+            return Some(Variable::Upvalue(idx));
+        }
+        return self.name_map.get(name).map(|idx| Variable::Global(*idx));
+    }
 
+    /// looks for name among locals of enclosing functions. if found, it is captured by every function
+    /// between declaring one and current, so that closure can take it from closure that creates it
+    fn resolve_upvalue(&mut self, name:&str) -> Option<usize> {
+        let (level, slot) = self.enclosing.iter().enumerate().rev()
+            .find_map(|(level, state)| {
+                state.locals.iter().rposition(|local| local.name==name).map(|slot| (level, slot))
+            })?;
+
+        self.enclosing[level].locals[slot].captured = true;
+        let mut capture = Capture{index:slot, is_local:true};
+        for state in &mut self.enclosing[level+1..] {
+            capture = Capture{index:Compiler::add_capture(&mut state.captures, capture), is_local:false};
+        }
+        return Some(Compiler::add_capture(&mut self.captures, capture));
+    }
 
-    fn find_variables(ast: &Expr, names:&mut HashMap<String, usize>) -> Result<(), String> {
-        /*
-        builds variable index & checks for name errors
-         */
-        match &ast.expr_type {
-            ExprType::AssignStmt(variable_name) => {
-                return if !names.contains_key(variable_name.as_str()) {
-                    Err(format!("undeclared variable {}", variable_name))
-                } else {
-                    Ok(())
-                }
-            }
+    fn add_capture(captures:&mut Vec<Capture>, capture:Capture) -> usize {
+        if let Some(idx) = captures.iter().position(|c| *c==capture) {
+            return idx;
+        }
+        captures.push(capture);
+        return captures.len()-1;
+    }
 
-            ExprType::VarDeclStmt(variable_name) => {
-                if !ast.children.is_empty() {
-                    Compiler::find_variables(ast.children.first().unwrap(),
-                    names)?;
-                }
+    /// allocates slot in variable area for new top-level variable
+    fn declare_global(&mut self, name:&str, span:Span) -> Result<usize, CompileError> {
+        if self.name_map.contains_key(name) {
+            return Err(CompileError::new(CompileErrorKind::Redefinition(name.to_string()), span));
+        }
 
+        let idx = self.variable_count;
+        self.variable_count += 1;
+        self.name_map.insert(name.to_string(), idx);
+        return Ok(idx);
+    }
 
-                if names.contains_key(variable_name.as_str()){
-                    return Err(format!("redefinition of variable {}", variable_name));
-                }else{
-                    names.insert(variable_name.to_string(), names.len());
-                    //define new variable at end
-                    return Ok(());
-                }
-            }
+    /// registers value on top of stack as local variable of current block. may shadow outer variables
+    fn declare_local(&mut self, name:&str, span:Span) -> Result<usize, CompileError> {
+        //unnamed locals are never looked up, so there may be several of them
+        let redefined = !name.is_empty() && self.locals.iter().rev()
+            .take_while(|local| local.depth==self.scope_depth)
+            .any(|local| local.name==name);
+        if redefined {
+            return Err(CompileError::new(CompileErrorKind::Redefinition(name.to_string()), span));
+        }
 
-            _ => {
-                for item in ast.children.as_slice() {
-                    Compiler::find_variables(item, names)?;
-                }
-            }
+        self.locals.push(Local{name:name.to_string(), depth:self.scope_depth, captured:false});
+        return Ok(self.locals.len()-1);
+    }
+
+    fn begin_scope(&mut self) {
+        self.scope_depth += 1;
+    }
+
+    /// forgets locals of innermost block and removes them from stack
+    fn end_scope(&mut self, code_chunk:&mut Chunk, span:Span) {
+        self.scope_depth -= 1;
+        let remaining = self.locals.iter().take_while(|local| local.depth<=self.scope_depth).count();
+        self.emit_pop_locals(code_chunk, remaining, span);
+        self.locals.truncate(remaining);
+    }
+
+    /// removes locals above first `remaining` ones from stack, captured ones are moved to their upvalues
+    fn emit_pop_locals(&self, code_chunk:&mut Chunk, remaining:usize, span:Span) {
+        for local in self.locals[remaining..].iter().rev() {
+            code_chunk.write(if local.captured {OpCode::CloseUpvalue} else {OpCode::Pop}, span);
         }
-        return Ok(());
     }
 
-    fn push_extensions(code_chunk:&mut Chunk, addr:usize){
+    fn push_extensions(code_chunk:&mut Chunk, addr:usize, span:Span){
         if addr<= 0xff {
             return;
         }
@@ -138,55 +308,295 @@ impl Compiler {
         let arr = arr.split_last().unwrap().1;
 
         for x in arr {
-            code_chunk.program.push(OpCode::Extend(*x));
+            code_chunk.write(OpCode::Extend(*x), span);
+        }
+
+    }
+
+    /// emits jump with fixed-size (3 byte) target to be filled by patch_jump. returns position of jump
+    fn emit_jump(code_chunk:&mut Chunk, jump:fn(u8) -> OpCode, span:Span) -> usize {
+        //target is not known yet, so reserve two extension slots to allow patching it without moving code
+        let position = code_chunk.program.len();
+        code_chunk.write(OpCode::Extend(0), span);
+        code_chunk.write(OpCode::Extend(0), span);
+        code_chunk.write(jump(0), span);
+        return position;
+    }
+
+    fn patch_jump(code_chunk:&mut Chunk, position:usize, target:usize) -> Result<(), CompileError> {
+        if target > 0xffffff {
+            return Err(CompileError::new(CompileErrorKind::JumpTooLong, code_chunk.spans[position]));
+        }
+        let [_, high, middle, low] = (target as u32).to_be_bytes();
+        code_chunk.program[position] = OpCode::Extend(high);
+        code_chunk.program[position+1] = OpCode::Extend(middle);
+        code_chunk.program[position+2] = match code_chunk.program[position+2] {
+            OpCode::Jump(_) => {OpCode::Jump(low)}
+            OpCode::JumpIfFalse(_) => {OpCode::JumpIfFalse(low)}
+            OpCode::JumpIfTrue(_) => {OpCode::JumpIfTrue(low)}
+            other => {other} //not a jump
+        };
+        return Ok(());
+    }
+
+    fn add_constant(code_chunk:&mut Chunk, constant:Constant) -> usize {
+        code_chunk.constant_pool.push(constant);
+        return code_chunk.constant_pool.len()-1;
+    }
+
+    fn emit_constant(code_chunk:&mut Chunk, constant:Constant, span:Span) {
+        let idx = Compiler::add_constant(code_chunk, constant);
+        Compiler::push_extensions(code_chunk, idx, span);
+        code_chunk.write(OpCode::LoadConst(idx as u8), span);
+    }
+
+    /// builtins are called by name unless variable or native with same name is visible
+    fn is_builtin_call(&mut self, ast:&Expr, builtin:&str) -> bool {
+        match &ast.children[0].expr_type {
+            ExprType::Variable(name) => {
+                name==builtin && !self.natives.contains_key(name) && self.resolve_variable(name).is_none()
+            }
+            _ => {false}
+        }
+    }
+
+    /// index and arity of native called by Call node
+    fn native_of_call(&mut self, ast:&Expr) -> Option<(usize, usize)> {
+        match &ast.children[0].expr_type {
+            ExprType::Variable(name) if self.resolve_variable(name).is_none() => {self.natives.get(name).copied()}
+            _ => {None}
+        }
+    }
+
+    /// compiles Assign or CompoundAssign node, assigned value is left on stack if keep_value is set
+    fn compile_assignment(&mut self, code_chunk:&mut Chunk, ast:&Expr, keep_value:bool) -> Result<(), CompileError> {
+        let (name, op) = match &ast.expr_type {
+            ExprType::Assign(name) => {(name, None)}
+            ExprType::CompoundAssign(name, op) => {(name, Some(*op))}
+            _ => {unreachable!()}
+        };
+        let idx = match self.resolve_variable(name) {
+            Some(idx) => {idx}
+            None => {
+                //assignment starts with variable name
+                let span = Span::at(ast.span.start, name.len());
+                return Err(CompileError::new(CompileErrorKind::UndeclaredVariable(name.clone()), span));
+            }
+        };
+
+        if let Some(op) = op {
+            //a += b is a = a + b, with variable resolved once
+            Compiler::emit_load(code_chunk, idx, ast.span);
+            self.compile_ast(code_chunk, &ast.children[0])?;
+            if let Some(opcode) = Compiler::binary_opcode(op) {
+                code_chunk.write(opcode, ast.span);
+            }
+        }else{
+            self.compile_ast(code_chunk, &ast.children[0])?;
+        }
+
+        if keep_value {
+            code_chunk.write(OpCode::Dup, ast.span);
+        }
+        Compiler::emit_store(code_chunk, idx, ast.span);
+        return Ok(());
+    }
+
+    /// instruction of binary operator, logical ones are compiled to jumps instead
+    fn binary_opcode(op:&str) -> Option<OpCode> {
+        Some(match op {
+            "+" => {OpCode::Add}
+            "-" => {OpCode::Sub}
+            "*" => {OpCode::Mult}
+            "/" => {OpCode::Div}
+            "div" => {OpCode::FloorDiv}
+            "%" => {OpCode::Mod}
+            "**" => {OpCode::Pow}
+            "&" => {OpCode::BitAnd}
+            "|" => {OpCode::BitOr}
+            "^" => {OpCode::BitXor}
+            "<<" => {OpCode::Shl}
+            ">>" => {OpCode::Shr}
+            "==" => {OpCode::Equal}
+            "!=" => {OpCode::NotEqual}
+            "<" => {OpCode::Less}
+            "<=" => {OpCode::LessEqual}
+            ">" => {OpCode::Greater}
+            ">=" => {OpCode::GreaterEqual}
+            "in" => {OpCode::Contains}
+            _ => {return None;}
+        })
+    }
+
+    fn emit_load(code_chunk:&mut Chunk, variable:Variable, span:Span) {
+        match variable {
+            Variable::Global(idx) => {
+                Compiler::push_extensions(code_chunk, idx, span);
+                code_chunk.write(OpCode::LoadVar(idx as u8), span);
+            }
+            Variable::Local(idx) => {
+                Compiler::push_extensions(code_chunk, idx, span);
+                code_chunk.write(OpCode::LoadLocal(idx as u8), span);
+            }
+            Variable::Upvalue(idx) => {
+                Compiler::push_extensions(code_chunk, idx, span);
+                code_chunk.write(OpCode::LoadUpvalue(idx as u8), span);
+            }
         }
+    }
 
+    fn emit_store(code_chunk:&mut Chunk, variable:Variable, span:Span) {
+        match variable {
+            Variable::Global(idx) => {
+                Compiler::push_extensions(code_chunk, idx, span);
+                code_chunk.write(OpCode::Store(idx as u8), span);
+            }
+            Variable::Local(idx) => {
+                Compiler::push_extensions(code_chunk, idx, span);
+                code_chunk.write(OpCode::StoreLocal(idx as u8), span);
+            }
+            Variable::Upvalue(idx) => {
+                Compiler::push_extensions(code_chunk, idx, span);
+                code_chunk.write(OpCode::StoreUpvalue(idx as u8), span);
+            }
+        }
+    }
+
+    /// compiles function body into its own chunk, parameters become first locals of call frame.
+    /// closure of it is pushed on stack
+    fn compile_function(&mut self, code_chunk:&mut Chunk, name:&str, params:&[String], body:&Expr, span:Span)
+        -> Result<(), CompileError> {
+        self.enclosing.push(FunctionState{
+            locals:std::mem::take(&mut self.locals),
+            scope_depth:self.scope_depth,
+            loops:std::mem::take(&mut self.loops),
+            captures:std::mem::take(&mut self.captures)
+        });
+        self.scope_depth = 1;
+        let mut function_chunk = Chunk::new();
+        let res = self.compile_function_body(&mut function_chunk, params, body, span);
+
+        //enclosing state is restored even if body fails, loops around function are still being compiled
+        let state = self.enclosing.pop().unwrap();
+        self.locals = state.locals;
+        self.scope_depth = state.scope_depth;
+        self.loops = state.loops;
+        let captures = std::mem::replace(&mut self.captures, state.captures);
+        res?;
+
+        let function = Function{name:name.to_string(), arity:params.len(), chunk:function_chunk, captures};
+        let idx = Compiler::add_constant(code_chunk, Constant::Function(Rc::new(function)));
+        Compiler::push_extensions(code_chunk, idx, span);
+        code_chunk.write(OpCode::Closure(idx as u8), span);
+        return Ok(());
     }
 
-    fn compile_ast(&mut self, code_chunk:&mut Chunk,  ast: &Expr) -> Result<(), String>{
+    fn compile_function_body(&mut self, function_chunk:&mut Chunk, params:&[String], body:&Expr, span:Span)
+        -> Result<(), CompileError> {
+        for param in params {
+            self.declare_local(param, span)?;
+        }
+        self.compile_ast(function_chunk, body)?;
+        //implicit return at the end of body
+        Compiler::emit_constant(function_chunk, Constant::Int(0), body.span);
+        function_chunk.write(OpCode::Return, body.span);
+        return Ok(());
+    }
+
+    /// compiles loop body and patches its break and continue jumps
+    fn compile_loop_body(&mut self, code_chunk:&mut Chunk, body:&Expr, continue_target:Option<usize>)
+        -> Result<LoopJumps, CompileError> {
+        self.loops.push(LoopJumps{breaks:Vec::new(), continues:Vec::new(), local_count:self.locals.len()});
+        let res = self.compile_ast(code_chunk, body);
+        let jumps = self.loops.pop().unwrap();
+        res?;
+
+        if let Some(target) = continue_target {
+            for jump in &jumps.continues {
+                Compiler::patch_jump(code_chunk, *jump, target)?;
+            }
+        }
+        return Ok(jumps);
+    }
+
+    fn compile_ast(&mut self, code_chunk:&mut Chunk,  ast: &Expr) -> Result<(), CompileError>{
         match &ast.expr_type {
-            ExprType::Op(c) => {
+            ExprType::Op(op) => {
                 self.compile_ast(code_chunk,  &ast.children[0])?;
                 self.compile_ast(code_chunk,  &ast.children[1])?;
 
-                match c {
-                    '+' => {code_chunk.program.push(OpCode::Add)}
-                    '-' => {code_chunk.program.push(OpCode::Sub)}
-                    '*' => {code_chunk.program.push(OpCode::Mult)}
-                    '/' => {code_chunk.program.push(OpCode::Div)}
-                    _ => {} //wont happen (hopefully)
+                if let Some(opcode) = Compiler::binary_opcode(op) {
+                    code_chunk.write(opcode, ast.span);
                 }
             }
 
-            ExprType::Literal(i) => {
-                let idx = code_chunk.constant_pool.len();
-                code_chunk.constant_pool.push(*i);
-                Compiler::push_extensions(code_chunk, idx);
-                code_chunk.program.push(OpCode::LoadConst(idx as u8));
+            ExprType::Logical(op) => {
+                //left operand decides whether right one is evaluated, its value is the result otherwise
+                self.compile_ast(code_chunk, &ast.children[0])?;
+                let jump = if *op=="and" {OpCode::JumpIfFalse} else {OpCode::JumpIfTrue};
+                let end_jump = Compiler::emit_jump(code_chunk, jump, ast.span);
+                code_chunk.write(OpCode::Pop, ast.span);
+                self.compile_ast(code_chunk, &ast.children[1])?;
+                Compiler::patch_jump(code_chunk, end_jump, code_chunk.program.len())?;
+            }
+
+            ExprType::Unary(op) => {
+                self.compile_ast(code_chunk, &ast.children[0])?;
+                match *op {
+                    "not" => {code_chunk.write(OpCode::Not, ast.span)}
+                    "-" => {code_chunk.write(OpCode::Negate, ast.span)}
+                    "+" => {code_chunk.write(OpCode::Positive, ast.span)}
+                    "~" => {code_chunk.write(OpCode::BitNot, ast.span)}
+                    _ => {}
+                }
+            }
+
+            ExprType::Bool(b) => {
+                code_chunk.write(if *b {OpCode::True} else {OpCode::False}, ast.span);
+            }
 
+            ExprType::Literal(i) => {
+                Compiler::emit_constant(code_chunk, Constant::Int(*i), ast.span);
+            }
+            ExprType::BigLiteral(n) => {
+                Compiler::emit_constant(code_chunk, Constant::BigInt(n.clone()), ast.span);
+            }
+            ExprType::Float(n) => {
+                Compiler::emit_constant(code_chunk, Constant::Float(*n), ast.span);
+            }
+            ExprType::Str(string) => {
+                Compiler::emit_constant(code_chunk, Constant::Str(string.as_str().into()), ast.span);
             }
             ExprType::Variable(name) => {
-                let idx = match self.name_map.get(name.as_str()){
-                    None => {return Err(format!("unknown variable {}", name));}
-                    Some(x) => {*x}
+                let idx = match self.resolve_variable(name){
+                    None => {return Err(CompileError::new(CompileErrorKind::UnknownVariable(name.clone()), ast.span));}
+                    Some(x) => {x}
                 };
 
-                Compiler::push_extensions(code_chunk, idx);
-                code_chunk.program.push(OpCode::LoadVar(idx as u8 ));
+                Compiler::emit_load(code_chunk, idx, ast.span);
             }
 
-            ExprType::AssignStmt(name) => {
-                //we know that names are fine resolved
-                let idx:usize = *self.name_map.get(name.as_str()).unwrap();
-
-                self.compile_ast(code_chunk, &ast.children[0])?;
+            ExprType::Assign(_) | ExprType::CompoundAssign(..) => {
+                self.compile_assignment(code_chunk, ast, true)?;
+            }
 
-                code_chunk.program.push(OpCode::Store(idx as u8));
+            ExprType::ExprStmt => {
+                let sub = &ast.children[0];
+                match sub.expr_type {
+                    //value of assignment would be popped right away, so it is not kept
+                    ExprType::Assign(_) | ExprType::CompoundAssign(..) => {
+                        self.compile_assignment(code_chunk, sub, false)?;
+                    }
+                    _ => {
+                        self.compile_ast(code_chunk, sub)?;
+                        code_chunk.write(OpCode::Pop, ast.span);
+                    }
+                }
             }
 
             ExprType::PrintStmt => {
                 self.compile_ast(code_chunk, &ast.children[0])?;
-                code_chunk.program.push(OpCode::Print);
+                code_chunk.write(OpCode::Print, ast.span);
             }
             ExprType::Program => {
                 for stmt in &ast.children{
@@ -195,15 +605,304 @@ impl Compiler {
             }
 
             ExprType::VarDeclStmt(varname) => {
-                if !ast.children.is_empty() { // has initializer
-                    self.compile_ast(code_chunk, ast.children.first().unwrap())?;
-                    let idx:usize = *self.name_map.get(varname).unwrap();
-                    Compiler::push_extensions(code_chunk, idx);
-                    code_chunk.program.push(OpCode::Store(idx as u8));
+                if self.scope_depth==0 {
+                    if !ast.children.is_empty() { // has initializer
+                        //compiled before declaration, so variable can't be used in own initializer
+                        self.compile_ast(code_chunk, ast.children.first().unwrap())?;
+                        let idx = self.declare_global(varname, ast.span)?;
+                        Compiler::emit_store(code_chunk, Variable::Global(idx), ast.span);
+                    }else{
+                        self.declare_global(varname, ast.span)?;
+                    }
+                }else{
+                    //value of local is left on stack, inside initializer name still refers to outer variable
+                    match ast.children.first() {
+                        Some(initializer) => {self.compile_ast(code_chunk, initializer)?;}
+                        None => {Compiler::emit_constant(code_chunk, Constant::Int(0), ast.span);}
+                    }
+                    self.declare_local(varname, ast.span)?;
+                }
+            }
+
+            ExprType::WhileStmt => {
+                let start = code_chunk.program.len();
+                self.compile_ast(code_chunk, &ast.children[0])?;
+                let exit_jump = Compiler::emit_jump(code_chunk, OpCode::JumpIfFalse, ast.span);
+                code_chunk.write(OpCode::Pop, ast.span); //condition
+
+                let jumps = self.compile_loop_body(code_chunk, &ast.children[1], Some(start))?;
+                let back_jump = Compiler::emit_jump(code_chunk, OpCode::Jump, ast.span);
+                Compiler::patch_jump(code_chunk, back_jump, start)?;
+
+                Compiler::patch_jump(code_chunk, exit_jump, code_chunk.program.len())?;
+                code_chunk.write(OpCode::Pop, ast.span); //condition
+
+                for jump in jumps.breaks {
+                    Compiler::patch_jump(code_chunk, jump, code_chunk.program.len())?;
+                }
+            }
+
+            ExprType::ForStmt(varname) => {
+                //loop variable lives in its own scope around the body
+                self.begin_scope();
+
+                self.compile_ast(code_chunk, &ast.children[0])?;
+                let counter = Variable::Local(self.declare_local(varname, ast.span)?);
+
+                //range end is evaluated once, before the loop. it is kept in unnamed local
+                self.compile_ast(code_chunk, &ast.children[1])?;
+                let end = Variable::Local(self.declare_local("", ast.span)?);
+
+                let start = code_chunk.program.len();
+                Compiler::emit_load(code_chunk, counter, ast.span);
+                Compiler::emit_load(code_chunk, end, ast.span);
+                code_chunk.write(OpCode::Less, ast.span);
+                let exit_jump = Compiler::emit_jump(code_chunk, OpCode::JumpIfFalse, ast.span);
+                code_chunk.write(OpCode::Pop, ast.span); //condition
+
+                let jumps = self.compile_loop_body(code_chunk, &ast.children[2], None)?;
+
+                //continue goes to increment
+                for jump in jumps.continues {
+                    Compiler::patch_jump(code_chunk, jump, code_chunk.program.len())?;
                 }
+                Compiler::emit_load(code_chunk, counter, ast.span);
+                Compiler::emit_constant(code_chunk, Constant::Int(1), ast.span);
+                code_chunk.write(OpCode::Add, ast.span);
+                Compiler::emit_store(code_chunk, counter, ast.span);
+                let back_jump = Compiler::emit_jump(code_chunk, OpCode::Jump, ast.span);
+                Compiler::patch_jump(code_chunk, back_jump, start)?;
+
+                Compiler::patch_jump(code_chunk, exit_jump, code_chunk.program.len())?;
+                code_chunk.write(OpCode::Pop, ast.span); //condition
+
+                for jump in jumps.breaks {
+                    Compiler::patch_jump(code_chunk, jump, code_chunk.program.len())?;
+                }
+                self.end_scope(code_chunk, ast.span);
+            }
+
+            ExprType::ForEachStmt(varname) => {
+                self.begin_scope();
+
+                //collection and position in it are kept in unnamed locals
+                self.compile_ast(code_chunk, &ast.children[0])?;
+                let collection = Variable::Local(self.declare_local("", ast.span)?);
+                Compiler::emit_constant(code_chunk, Constant::Int(0), ast.span);
+                let position = Variable::Local(self.declare_local("", ast.span)?);
+                Compiler::emit_constant(code_chunk, Constant::Int(0), ast.span);
+                let item = Variable::Local(self.declare_local(varname, ast.span)?);
+
+                //length is checked on every iteration, map may grow inside of loop
+                let start = code_chunk.program.len();
+                Compiler::emit_load(code_chunk, position, ast.span);
+                Compiler::emit_load(code_chunk, collection, ast.span);
+                code_chunk.write(OpCode::Len, ast.span);
+                code_chunk.write(OpCode::Less, ast.span);
+                let exit_jump = Compiler::emit_jump(code_chunk, OpCode::JumpIfFalse, ast.span);
+                code_chunk.write(OpCode::Pop, ast.span); //condition
+
+                Compiler::emit_load(code_chunk, collection, ast.span);
+                Compiler::emit_load(code_chunk, position, ast.span);
+                code_chunk.write(OpCode::IterItem, ast.span);
+                Compiler::emit_store(code_chunk, item, ast.span);
+
+                let jumps = self.compile_loop_body(code_chunk, &ast.children[1], None)?;
+
+                for jump in jumps.continues {
+                    Compiler::patch_jump(code_chunk, jump, code_chunk.program.len())?;
+                }
+                Compiler::emit_load(code_chunk, position, ast.span);
+                Compiler::emit_constant(code_chunk, Constant::Int(1), ast.span);
+                code_chunk.write(OpCode::Add, ast.span);
+                Compiler::emit_store(code_chunk, position, ast.span);
+                let back_jump = Compiler::emit_jump(code_chunk, OpCode::Jump, ast.span);
+                Compiler::patch_jump(code_chunk, back_jump, start)?;
+
+                Compiler::patch_jump(code_chunk, exit_jump, code_chunk.program.len())?;
+                code_chunk.write(OpCode::Pop, ast.span); //condition
+
+                for jump in jumps.breaks {
+                    Compiler::patch_jump(code_chunk, jump, code_chunk.program.len())?;
+                }
+                self.end_scope(code_chunk, ast.span);
+            }
+
+            ExprType::BreakStmt | ExprType::ContinueStmt => {
+                let is_break = matches!(ast.expr_type, ExprType::BreakStmt);
+                if self.loops.is_empty() {
+                    let keyword = if is_break {"break"} else {"continue"};
+                    return Err(CompileError::new(CompileErrorKind::JumpOutsideLoop(keyword), ast.span));
+                }
+                //leave locals of blocks inside loop body
+                let loop_locals = self.loops.last().unwrap().local_count;
+                self.emit_pop_locals(code_chunk, loop_locals, ast.span);
+                let jump = Compiler::emit_jump(code_chunk, OpCode::Jump, ast.span);
+                let innermost = self.loops.last_mut().unwrap();
+                if is_break {innermost.breaks.push(jump);} else {innermost.continues.push(jump);}
+            }
+
+            ExprType::FnDecl(name, params) => {
+                //function is declared before its body, so it can call itself.
+                //local one takes slot where its closure will be pushed
+                if self.scope_depth==0 {
+                    let idx = self.declare_global(name, ast.span)?;
+                    self.compile_function(code_chunk, name, params, &ast.children[0], ast.span)?;
+                    Compiler::emit_store(code_chunk, Variable::Global(idx), ast.span);
+                }else{
+                    self.declare_local(name, ast.span)?;
+                    self.compile_function(code_chunk, name, params, &ast.children[0], ast.span)?;
+                }
+            }
+
+            ExprType::Lambda(params) => {
+                self.compile_function(code_chunk, "lambda", params, &ast.children[0], ast.span)?;
+            }
+
+            ExprType::Call if self.is_builtin_call(ast, "len") => {
+                if ast.children.len()!=2 {
+                    let kind = CompileErrorKind::ArityMismatch {name: "len".to_string(), expected: 1, found: ast.children.len()-1};
+                    return Err(CompileError::new(kind, ast.span));
+                }
+                self.compile_ast(code_chunk, &ast.children[1])?;
+                code_chunk.write(OpCode::Len, ast.span);
+            }
+
+            ExprType::Call if self.native_of_call(ast).is_some() => {
+                let (index, arity) = self.native_of_call(ast).unwrap();
+                if ast.children.len()-1!=arity {
+                    let name = match &ast.children[0].expr_type {
+                        ExprType::Variable(name) => {name.clone()}
+                        _ => {unreachable!()}
+                    };
+                    let kind = CompileErrorKind::ArityMismatch {name, expected: arity, found: ast.children.len()-1};
+                    return Err(CompileError::new(kind, ast.span));
+                }
+                for child in &ast.children[1..] {
+                    self.compile_ast(code_chunk, child)?;
+                }
+                Compiler::push_extensions(code_chunk, index, ast.span);
+                code_chunk.write(OpCode::CallNative(index as u8), ast.span);
+            }
+
+            ExprType::Call => {
+                //callee goes first, then arguments
+                for child in &ast.children {
+                    self.compile_ast(code_chunk, child)?;
+                }
+                let argc = ast.children.len()-1;
+                Compiler::push_extensions(code_chunk, argc, ast.span);
+                code_chunk.write(OpCode::Call(argc as u8), ast.span);
+            }
+
+            ExprType::List => {
+                for child in &ast.children {
+                    self.compile_ast(code_chunk, child)?;
+                }
+                let count = ast.children.len();
+                Compiler::push_extensions(code_chunk, count, ast.span);
+                code_chunk.write(OpCode::BuildList(count as u8), ast.span);
+            }
+
+            ExprType::Map => {
+                for child in &ast.children {
+                    self.compile_ast(code_chunk, child)?;
+                }
+                let count = ast.children.len()/2;
+                Compiler::push_extensions(code_chunk, count, ast.span);
+                code_chunk.write(OpCode::BuildMap(count as u8), ast.span);
+            }
+
+            ExprType::Index => {
+                self.compile_ast(code_chunk, &ast.children[0])?;
+                self.compile_ast(code_chunk, &ast.children[1])?;
+                code_chunk.write(OpCode::Index, ast.span);
+            }
+
+            ExprType::IndexAssign => {
+                for child in &ast.children {
+                    self.compile_ast(code_chunk, child)?;
+                }
+                code_chunk.write(OpCode::SetIndex, ast.span);
+            }
+
+            ExprType::CompoundIndexAssign(op) => {
+                //list and index are evaluated once and used both for reading and writing
+                self.compile_ast(code_chunk, &ast.children[0])?;
+                self.compile_ast(code_chunk, &ast.children[1])?;
+                code_chunk.write(OpCode::DupTwo, ast.span);
+                code_chunk.write(OpCode::Index, ast.span);
+                self.compile_ast(code_chunk, &ast.children[2])?;
+                if let Some(opcode) = Compiler::binary_opcode(op) {
+                    code_chunk.write(opcode, ast.span);
+                }
+                code_chunk.write(OpCode::SetIndex, ast.span);
+            }
+
+            ExprType::ReturnStmt => {
+                if self.enclosing.is_empty() {
+                    return Err(CompileError::new(CompileErrorKind::ReturnOutsideFunction, ast.span));
+                }
+                match ast.children.first() {
+                    Some(value) => {self.compile_ast(code_chunk, value)?;}
+                    None => {Compiler::emit_constant(code_chunk, Constant::Int(0), ast.span);}
+                }
+                code_chunk.write(OpCode::Return, ast.span);
+            }
+
+            ExprType::Block => {
+                self.begin_scope();
+                for stmt in &ast.children {
+                    self.compile_ast(code_chunk, stmt)?;
+                }
+                self.end_scope(code_chunk, ast.span);
+            }
+
+            ExprType::IfStmt => {
+                self.compile_ast(code_chunk, &ast.children[0])?;
+                let else_jump = Compiler::emit_jump(code_chunk, OpCode::JumpIfFalse, ast.span);
+                code_chunk.write(OpCode::Pop, ast.span); //condition
+                self.compile_ast(code_chunk, &ast.children[1])?;
+                let end_jump = Compiler::emit_jump(code_chunk, OpCode::Jump, ast.span);
+
+                Compiler::patch_jump(code_chunk, else_jump, code_chunk.program.len())?;
+                code_chunk.write(OpCode::Pop, ast.span); //condition
+                if let Some(else_branch) = ast.children.get(2) {
+                    self.compile_ast(code_chunk, else_branch)?;
+                }
+                Compiler::patch_jump(code_chunk, end_jump, code_chunk.program.len())?;
             }
         }
         return Ok(());
     }
 
-}
\ No newline at end of file
+}
+impl Default for Compiler {
+    fn default() -> Self {
+        Compiler::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+    use crate::parser::parse;
+    use crate::vm::IntegerMode;
+
+    fn compile(compiler:&mut Compiler, source:&str) -> Result<Chunk, CompileError> {
+        let tokens = tokenize(source, IntegerMode::Fixed).unwrap();
+        let ast = parse(&tokens).unwrap();
+        return compiler.continue_compile(&ast);
+    }
+
+    #[test]
+    fn error_in_function_inside_loop() {
+        let mut compiler = Compiler::new();
+        assert!(compile(&mut compiler, "while (true) { fn h() { return y; } }").is_err());
+        assert!(compile(&mut compiler, "for i in 0..3 { var f = fn () { continue; }; }").is_err());
+        assert!(compile(&mut compiler, "for i in 0..3 { fn g(a, a) { return a; } break; }").is_err());
+        //compiler is still usable after errors
+        assert!(compile(&mut compiler, "var n = 0; while (n < 3) { var f = fn () { return n; }; n = n + 1; }").is_ok());
+    }
+}
@@ -0,0 +1,173 @@
+use crate::lexer::{Span, TokenIndex};
+use crate::error::{Error, LexicalError, SyntaxError, SyntaxErrorKind, CompileError, RuntimeError};
+use std::fmt::{Display, Formatter};
+use std::fmt;
+
+/*
+rustc-like rendering of errors:
+
+error: Expected semicolon, got identifier b
+ --> program.txt:2:9
+  |
+2 | print a b;
+  |         ^
+  = note: ...
+ */
+
+#[derive(Copy, Clone, Debug)]
+pub enum Severity {
+    Error,
+    Note
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            Severity::Error => {"error"}
+            Severity::Note => {"note"}
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Note {
+    pub message: String,
+    pub span: Option<Span>
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    pub notes: Vec<Note>
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: String, span: Option<Span>) -> Diagnostic {
+        Diagnostic{severity, message, span, notes: Vec::new()}
+    }
+
+    pub fn error(message: String, span: Option<Span>) -> Diagnostic {
+        Diagnostic::new(Severity::Error, message, span)
+    }
+
+    pub fn with_note(mut self, message: String, span: Option<Span>) -> Diagnostic {
+        self.notes.push(Note{message, span});
+        self
+    }
+
+    /// renders diagnostic against source text. source_name is shown in location line (file name or <repl>)
+    pub fn render(&self, source_name: &str, source: &str) -> String {
+        let mut res = format!("{}: {}\n", self.severity, self.message);
+        if let Some(span) = self.span {
+            res.push_str(&render_snippet(source_name, source, span));
+        }
+
+        for note in &self.notes {
+            match note.span {
+                Some(span) => {
+                    res.push_str(&format!("{}: {}\n", Severity::Note, note.message));
+                    res.push_str(&render_snippet(source_name, source, span));
+                }
+                None => {
+                    res.push_str(&format!("  = {}: {}\n", Severity::Note, note.message));
+                }
+            }
+        }
+        return res;
+    }
+}
+
+fn render_snippet(source_name: &str, source: &str, span: Span) -> String {
+    let line = source.lines().nth(span.start.line_number).unwrap_or("");
+    let line_no = (span.start.line_number+1).to_string();
+    let gutter = " ".repeat(line_no.len());
+
+    //positions are byte offsets, clamp them to line and convert to characters for display
+    let start = clamp_to_char_boundary(line, span.start.index);
+    let end = if span.end.line_number==span.start.line_number {
+        clamp_to_char_boundary(line, span.end.index)
+    }else{
+        line.len() //multiline span, underline till end of first line
+    };
+
+    //keep tabs so that caret lines up with the source line
+    let padding: String = line[..start].chars().map(|c| if c=='\t' {'\t'} else {' '}).collect();
+    let caret_count = line[start..end.max(start)].chars().count().max(1);
+
+    let mut res = String::new();
+    res.push_str(&format!("{}--> {}:{}:{}\n", gutter, source_name, line_no, line[..start].chars().count()+1));
+    res.push_str(&format!("{} |\n", gutter));
+    res.push_str(&format!("{} | {}\n", line_no, line));
+    res.push_str(&format!("{} | {}{}\n", gutter, padding, "^".repeat(caret_count)));
+    return res;
+}
+
+fn clamp_to_char_boundary(line: &str, index: usize) -> usize {
+    let mut index = index.min(line.len());
+    while !line.is_char_boundary(index) {
+        index -= 1;
+    }
+    return index;
+}
+
+fn point(position: TokenIndex) -> Span {
+    Span::at(position, 1)
+}
+
+impl From<&LexicalError> for Diagnostic {
+    fn from(e: &LexicalError) -> Self {
+        Diagnostic::error(e.message(), Some(e.span))
+    }
+}
+
+impl From<&SyntaxError> for Diagnostic {
+    fn from(e: &SyntaxError) -> Self {
+        let mut res = Diagnostic::error(e.message(), Some(e.span));
+        if let SyntaxErrorKind::UnclosedBracket {opening, ..} = &e.kind {
+            res = res.with_note("unclosed '(' is here".to_string(), Some(point(*opening)));
+        }
+        if let Some((message, position)) = &e.context {
+            res = res.with_note(message.clone(), Some(point(*position)));
+        }
+        return res;
+    }
+}
+
+impl From<&CompileError> for Diagnostic {
+    fn from(e: &CompileError) -> Self {
+        Diagnostic::error(e.message(), Some(e.span))
+    }
+}
+
+impl From<&RuntimeError> for Diagnostic {
+    fn from(e: &RuntimeError) -> Self {
+        let mut res = Diagnostic::error(e.message(), e.span);
+        if let Some(opcode) = &e.opcode {
+            res = res.with_note(format!("while executing {}", opcode), None);
+        }
+        return res;
+    }
+}
+
+impl Error {
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            Error::Lexical(e) => {vec![e.into()]}
+            Error::Syntax(errors) => {errors.iter().map(|e| e.into()).collect()}
+            Error::Compile(e) => {vec![e.into()]}
+            Error::Runtime(e) => {vec![e.into()]}
+            Error::Io(e) => {vec![Diagnostic::error(e.to_string(), None)]}
+        }
+    }
+}
+
+/// renders every diagnostic of error, separated by empty lines
+pub fn render_error(error: &Error, source_name: &str, source: &str) -> String {
+    error.diagnostics()
+        .iter()
+        .map(|d| d.render(source_name, source))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
@@ -0,0 +1,339 @@
+use crate::lexer::{Span, TokenIndex};
+use crate::vm::OpCode;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::sync::Arc;
+
+/*
+error types for every stage of the pipeline.
+each stage has its own error struct carrying a kind (to match on) and the span it refers to,
+Display reproduces the plain text messages
+ */
+
+#[derive(Debug, Clone)]
+pub enum LexicalErrorKind {
+    UnknownCharacter(char),
+    UnterminatedComment,
+    UnterminatedString,
+    UnknownEscape(char),
+    /// integer literal does not fit into i32 and VM does not use big integers
+    IntegerLiteralOverflow,
+}
+
+#[derive(Debug, Clone)]
+pub struct LexicalError {
+    pub kind: LexicalErrorKind,
+    pub span: Span
+}
+
+impl LexicalError {
+    /// message without position, location is reported separately
+    pub fn message(&self) -> String {
+        match &self.kind {
+            LexicalErrorKind::UnknownCharacter(c) => {format!("unknown character {}", c)}
+            LexicalErrorKind::UnterminatedComment => {"unterminated multiline comment".to_string()}
+            LexicalErrorKind::UnterminatedString => {"unterminated string".to_string()}
+            LexicalErrorKind::UnknownEscape(c) => {format!("unknown escape sequence \\{}", c)}
+            LexicalErrorKind::IntegerLiteralOverflow => {"integer literal is too large".to_string()}
+        }
+    }
+}
+
+impl Display for LexicalError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.message(), self.span.start)
+    }
+}
+
+impl std::error::Error for LexicalError {}
+
+
+#[derive(Debug, Clone)]
+pub enum SyntaxErrorKind {
+    Expected{expected: String, found: String},
+    UnexpectedToken(String),
+    UnexpectedEnd,
+    UnclosedBracket{opening: TokenIndex, found: String},
+    /// left side of assignment is not a variable
+    InvalidAssignmentTarget,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyntaxError {
+    pub kind: SyntaxErrorKind,
+    pub span: Span,
+    /// what the parser was trying to do when it failed, e.g. ("expected expression after print", <print position>)
+    pub context: Option<(String, TokenIndex)>
+}
+
+impl SyntaxError {
+    pub fn new(kind: SyntaxErrorKind, span: Span) -> SyntaxError {
+        SyntaxError{kind, span, context: None}
+    }
+
+    pub fn with_context(mut self, message: &str, position: TokenIndex) -> SyntaxError {
+        self.context = Some((message.to_string(), position));
+        self
+    }
+
+    /// message without position, location is reported separately
+    pub fn message(&self) -> String {
+        match &self.kind {
+            SyntaxErrorKind::Expected {expected, found} => {format!("Expected {}, got {}", expected, found)}
+            SyntaxErrorKind::UnexpectedToken(found) => {format!("unexpected token {}", found)}
+            SyntaxErrorKind::UnexpectedEnd => {"unexpected end".to_string()}
+            SyntaxErrorKind::UnclosedBracket {found, ..} => {format!("expected ')', got {}", found)}
+            SyntaxErrorKind::InvalidAssignmentTarget => {"invalid assignment target".to_string()}
+        }
+    }
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.message(), self.span.start)?;
+        if let SyntaxErrorKind::UnclosedBracket {opening, ..} = &self.kind {
+            write!(f, "\nfor opening '(' at {}", opening)?;
+        }
+        if let Some((message, position)) = &self.context {
+            write!(f, "\n{} at {}", message, position)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+
+#[derive(Debug, Clone)]
+pub enum CompileErrorKind {
+    /// assignment to a variable that was never declared
+    UndeclaredVariable(String),
+    /// read of a variable that was never declared
+    UnknownVariable(String),
+    Redefinition(String),
+    /// jump target does not fit into jump operand
+    JumpTooLong,
+    /// break or continue used outside of loop
+    JumpOutsideLoop(&'static str),
+    ReturnOutsideFunction,
+    /// call of builtin function with wrong amount of arguments
+    ArityMismatch{name: String, expected: usize, found: usize},
+}
+
+/// name-resolution errors reported by the compiler
+#[derive(Debug, Clone)]
+pub struct CompileError {
+    pub kind: CompileErrorKind,
+    pub span: Span
+}
+
+impl CompileError {
+    pub fn new(kind: CompileErrorKind, span: Span) -> CompileError {
+        CompileError{kind, span}
+    }
+}
+
+impl CompileError {
+    pub fn message(&self) -> String {
+        match &self.kind {
+            CompileErrorKind::UndeclaredVariable(name) => {format!("undeclared variable {}", name)}
+            CompileErrorKind::UnknownVariable(name) => {format!("unknown variable {}", name)}
+            CompileErrorKind::Redefinition(name) => {format!("redefinition of variable {}", name)}
+            CompileErrorKind::JumpTooLong => {"too much code to jump over".to_string()}
+            CompileErrorKind::JumpOutsideLoop(keyword) => {format!("{} outside of loop", keyword)}
+            CompileErrorKind::ReturnOutsideFunction => {"return outside of function".to_string()}
+            CompileErrorKind::ArityMismatch {name, expected, found} => {
+                format!("{} expects {} arguments, got {}", name, expected, found)
+            }
+        }
+    }
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.message(), self.span.start)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+
+/// resource limited by Limits of VM
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Limit {
+    Instructions,
+    StackLength,
+    HeapBytes
+}
+
+#[derive(Debug, Clone)]
+pub enum RuntimeErrorKind {
+    StackUnderflow,
+    ZeroDivision,
+    /// integer does not fit into i32
+    IntegerOverflow,
+    BadVariableIndex,
+    /// operand of wrong type, e.g. condition that is not bool
+    TypeMismatch{expected: &'static str, found: &'static str},
+    /// binary operator applied to unsupported pair of types
+    BadOperands{operator: &'static str, left: &'static str, right: &'static str},
+    /// call of value that is not a function
+    NotCallable(&'static str),
+    ArityMismatch{expected: usize, found: usize},
+    /// too many nested calls
+    StackOverflow,
+    /// shift by negative amount of bits
+    NegativeShift,
+    /// index operator applied to value that is not a collection
+    NotIndexable(&'static str),
+    IndexOutOfBounds{index: String, length: usize},
+    /// map key of type other than int or string
+    UnhashableKey(&'static str),
+    KeyNotFound(String),
+    /// native function reported failure
+    NativeError{name: String, message: String},
+    /// print statement failed to write to output
+    OutputError(String),
+    LimitExceeded(Limit),
+    /// stopped by interrupt flag
+    Interrupted,
+}
+
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    /// source of failing instruction, taken from chunk's span table
+    pub span: Option<Span>,
+    pub opcode: Option<OpCode>
+}
+
+impl RuntimeError {
+    pub fn message(&self) -> String {
+        match &self.kind {
+            RuntimeErrorKind::StackUnderflow => {"stack underflow".to_string()}
+            RuntimeErrorKind::ZeroDivision => {"zero division".to_string()}
+            RuntimeErrorKind::IntegerOverflow => {"integer overflow".to_string()}
+            RuntimeErrorKind::BadVariableIndex => {"value indexation error".to_string()}
+            RuntimeErrorKind::TypeMismatch {expected, found} => {
+                format!("type mismatch: expected {}, found {}", expected, found)
+            }
+            RuntimeErrorKind::BadOperands {operator, left, right} => {
+                format!("unsupported operand types for {}: {} and {}", operator, left, right)
+            }
+            RuntimeErrorKind::NotCallable(found) => {format!("{} is not callable", found)}
+            RuntimeErrorKind::ArityMismatch {expected, found} => {
+                format!("expected {} arguments, got {}", expected, found)
+            }
+            RuntimeErrorKind::StackOverflow => {"stack overflow".to_string()}
+            RuntimeErrorKind::NegativeShift => {"negative shift count".to_string()}
+            RuntimeErrorKind::NotIndexable(found) => {format!("{} is not indexable", found)}
+            RuntimeErrorKind::IndexOutOfBounds {index, length} => {
+                format!("index {} is out of bounds for list of length {}", index, length)
+            }
+            RuntimeErrorKind::UnhashableKey(found) => {format!("{} cannot be used as map key", found)}
+            RuntimeErrorKind::KeyNotFound(key) => {format!("key {} not found in map", key)}
+            RuntimeErrorKind::NativeError {name, message} => {format!("{}: {}", name, message)}
+            RuntimeErrorKind::OutputError(message) => {format!("failed to write output: {}", message)}
+            RuntimeErrorKind::LimitExceeded(limit) => {
+                format!("{} limit exceeded", match limit {
+                    Limit::Instructions => {"instruction"}
+                    Limit::StackLength => {"stack length"}
+                    Limit::HeapBytes => {"heap size"}
+                })
+            }
+            RuntimeErrorKind::Interrupted => {"execution interrupted".to_string()}
+        }
+    }
+}
+
+/// error of host call made outside of running code, it has no location
+impl From<RuntimeErrorKind> for RuntimeError {
+    fn from(kind: RuntimeErrorKind) -> Self {
+        RuntimeError{kind, span: None, opcode: None}
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message())?;
+        if let Some(span) = &self.span {
+            write!(f, " at {}", span.start)?;
+        }
+        if let Some(opcode) = &self.opcode {
+            write!(f, " while executing {}", opcode)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+
+/// any error produced while running source code through the whole pipeline
+#[derive(Debug, Clone)]
+pub enum Error {
+    Lexical(LexicalError),
+    /// parser recovers after errors, so it may report several of them
+    Syntax(Vec<SyntaxError>),
+    Compile(CompileError),
+    Runtime(RuntimeError),
+    /// source file could not be read
+    Io(Arc<std::io::Error>),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Lexical(e) => {write!(f, "{}", e)}
+            Error::Syntax(errors) => {
+                let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", messages.join("\n"))
+            }
+            Error::Compile(e) => {write!(f, "{}", e)}
+            Error::Runtime(e) => {write!(f, "{}", e)}
+            Error::Io(e) => {write!(f, "{}", e)}
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Lexical(e) => {Some(e)}
+            Error::Syntax(errors) => {errors.first().map(|e| e as &(dyn std::error::Error + 'static))}
+            Error::Compile(e) => {Some(e)}
+            Error::Runtime(e) => {Some(e)}
+            Error::Io(e) => {Some(e.as_ref())}
+        }
+    }
+}
+
+impl From<LexicalError> for Error {
+    fn from(e: LexicalError) -> Self {
+        Error::Lexical(e)
+    }
+}
+
+impl From<Vec<SyntaxError>> for Error {
+    fn from(e: Vec<SyntaxError>) -> Self {
+        Error::Syntax(e)
+    }
+}
+
+impl From<CompileError> for Error {
+    fn from(e: CompileError) -> Self {
+        Error::Compile(e)
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(e: RuntimeError) -> Self {
+        Error::Runtime(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(Arc::new(e))
+    }
+}
@@ -0,0 +1,507 @@
+use crate::vm::{Value, Closure, Map, MapKey, Upvalue};
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::mem::size_of;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/*
+managed heap of the VM. it holds objects that may reference other values (lists, maps and closures),
+values refer to them by handle. such objects may form cycles, so they are freed by mark-and-sweep
+collection, rooted in values the VM can still reach: its stack (globals, locals and temporaries)
+and closures of call frames.
+strings and big integers cannot reference anything and stay reference counted.
+ */
+
+/// handle of object in heap
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ObjRef(usize);
+
+pub enum Object {
+    List(Vec<Value>),
+    Map(Map),
+    Closure(Closure)
+}
+
+impl Object {
+    /// approximate amount of memory taken by object
+    fn size(&self) -> usize {
+        size_of::<Object>() + match self {
+            Object::List(items) => {items.capacity()*size_of::<Value>()}
+            //entry and its position in index
+            Object::Map(map) => {map.len()*MAP_ENTRY_SIZE}
+            Object::Closure(closure) => {closure.upvalues.len()*size_of::<usize>()}
+        }
+    }
+}
+
+/// approximate amount of memory taken by one entry of map
+pub const MAP_ENTRY_SIZE: usize = size_of::<(MapKey, Value)>() + size_of::<(MapKey, usize)>();
+
+/// approximate amount of memory taken by string or big integer value refers to
+fn value_size(value: &Value) -> usize {
+    match value {
+        Value::Str(s) => {s.len()}
+        Value::BigInt(n) => {n.size()}
+        _ => {0}
+    }
+}
+
+/// collection does not happen before this many bytes are allocated, unless configured otherwise
+pub const DEFAULT_GC_THRESHOLD: usize = 1024*1024;
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct HeapStats {
+    /// objects allocated since heap was created
+    pub allocations: usize,
+    pub collections: usize,
+    /// objects freed by collections
+    pub freed: usize,
+    pub live_objects: usize,
+    /// approximate size of live objects
+    pub bytes: usize
+}
+
+pub struct Heap {
+    /// freed slots are reused by later allocations
+    objects: Vec<Option<Object>>,
+    marks: Vec<bool>,
+    free: Vec<usize>,
+    bytes: usize,
+    /// strings and big integers are not objects, but they are counted as part of heap:
+    /// when instruction creates them, and again by every collection, which finds the ones still in use
+    value_bytes: usize,
+    /// minimal amount of bytes that triggers collection
+    threshold: usize,
+    next_collection: usize,
+    stats: HeapStats
+}
+
+impl Heap {
+    pub fn new(threshold: usize) -> Heap {
+        Heap{
+            objects: Vec::new(),
+            marks: Vec::new(),
+            free: Vec::new(),
+            bytes: 0,
+            value_bytes: 0,
+            threshold,
+            next_collection: threshold,
+            stats: HeapStats::default()
+        }
+    }
+
+    /// never collects, VM decides when to do it so that values it holds outside of stack survive
+    pub fn alloc(&mut self, object: Object) -> ObjRef {
+        self.bytes += object.size();
+        self.stats.allocations += 1;
+        match self.free.pop() {
+            Some(idx) => {
+                self.objects[idx] = Some(object);
+                return ObjRef(idx);
+            }
+            None => {
+                self.objects.push(Some(object));
+                self.marks.push(false);
+                return ObjRef(self.objects.len()-1);
+            }
+        }
+    }
+
+    /// accounts memory taken by object after it grew
+    pub fn grow(&mut self, bytes: usize) {
+        self.bytes += bytes;
+    }
+
+    /// counts string or big integer created by instruction
+    pub fn account_value(&mut self, value: &Value) {
+        self.value_bytes += value_size(value);
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.bytes()>self.next_collection
+    }
+
+    /// approximate size of objects, strings and big integers, including garbage not yet collected
+    pub fn bytes(&self) -> usize {
+        self.bytes+self.value_bytes
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        HeapStats{
+            live_objects: self.objects.len()-self.free.len(),
+            bytes: self.bytes(),
+            ..self.stats
+        }
+    }
+
+    fn get(&self, obj: ObjRef) -> &Object {
+        self.objects[obj.0].as_ref().expect("use of freed object")
+    }
+
+    fn get_mut(&mut self, obj: ObjRef) -> &mut Object {
+        self.objects[obj.0].as_mut().expect("use of freed object")
+    }
+
+    //handles are created together with values of matching type, so kind of object is known
+
+    pub fn list(&self, obj: ObjRef) -> &Vec<Value> {
+        match self.get(obj) {
+            Object::List(items) => {items}
+            _ => {unreachable!("object is not a list")}
+        }
+    }
+
+    pub fn list_mut(&mut self, obj: ObjRef) -> &mut Vec<Value> {
+        match self.get_mut(obj) {
+            Object::List(items) => {items}
+            _ => {unreachable!("object is not a list")}
+        }
+    }
+
+    pub fn map(&self, obj: ObjRef) -> &Map {
+        match self.get(obj) {
+            Object::Map(map) => {map}
+            _ => {unreachable!("object is not a map")}
+        }
+    }
+
+    pub fn map_mut(&mut self, obj: ObjRef) -> &mut Map {
+        match self.get_mut(obj) {
+            Object::Map(map) => {map}
+            _ => {unreachable!("object is not a map")}
+        }
+    }
+
+    pub fn closure(&self, obj: ObjRef) -> &Closure {
+        match self.get(obj) {
+            Object::Closure(closure) => {closure}
+            _ => {unreachable!("object is not a closure")}
+        }
+    }
+
+    /// frees every object that can not be reached from roots
+    pub fn collect<'a>(&mut self, roots: impl Iterator<Item=&'a Value>, root_objects: impl Iterator<Item=ObjRef>) {
+        //mark, strings and big integers that are still used are counted along the way
+        let mut gray: Vec<ObjRef> = root_objects.collect();
+        let mut reachable = ReachableValues::default();
+        for value in roots {
+            reachable.visit(value, &mut gray);
+        }
+        while let Some(obj) = gray.pop() {
+            if self.marks[obj.0] {
+                continue;
+            }
+            self.marks[obj.0] = true;
+            match self.get(obj) {
+                Object::List(items) => {
+                    for item in items {
+                        reachable.visit(item, &mut gray);
+                    }
+                }
+                Object::Map(map) => {
+                    for (key, value) in map.entries() {
+                        reachable.visit(&key.to_value(), &mut gray);
+                        reachable.visit(value, &mut gray);
+                    }
+                }
+                Object::Closure(closure) => {
+                    //open upvalues point to stack, which is a root anyway
+                    for upvalue in &closure.upvalues {
+                        if let Upvalue::Closed(value) = &*upvalue.borrow() {
+                            reachable.visit(value, &mut gray);
+                        }
+                    }
+                }
+            }
+        }
+        self.value_bytes = reachable.bytes;
+
+        //sweep
+        for idx in 0..self.objects.len() {
+            if self.marks[idx] {
+                self.marks[idx] = false;
+                continue;
+            }
+            if let Some(object) = self.objects[idx].take() {
+                self.bytes -= object.size();
+                self.free.push(idx);
+                self.stats.freed += 1;
+            }
+        }
+
+        self.stats.collections += 1;
+        self.next_collection = self.threshold.max(self.bytes()*2);
+    }
+
+    /// values of different types are never equal, except for int and float.
+    /// lists and maps are equal when their contents are
+    pub fn values_equal(&self, a: &Value, b: &Value) -> bool {
+        //pairs left to compare. pair of objects met again is not compared twice, so cyclic structures terminate
+        let mut pending: Vec<(&Value, &Value)> = vec![(a, b)];
+        let mut compared: HashSet<(ObjRef, ObjRef)> = HashSet::new();
+        while let Some((a, b)) = pending.pop() {
+            match (a, b) {
+                (Value::List(x), Value::List(y)) => {
+                    if x==y || !compared.insert((*x, *y)) {
+                        continue;
+                    }
+                    let (xs, ys) = (self.list(*x), self.list(*y));
+                    if xs.len()!=ys.len() {
+                        return false;
+                    }
+                    pending.extend(xs.iter().zip(ys.iter()));
+                }
+                (Value::Map(x), Value::Map(y)) => {
+                    if x==y || !compared.insert((*x, *y)) {
+                        continue;
+                    }
+                    //order of keys does not matter
+                    let (xs, ys) = (self.map(*x), self.map(*y));
+                    if xs.len()!=ys.len() {
+                        return false;
+                    }
+                    for (key, value) in xs.entries() {
+                        match ys.get(key) {
+                            Some(other) => {pending.push((value, other));}
+                            None => {return false;}
+                        }
+                    }
+                }
+                _ => {
+                    if a!=b {
+                        return false;
+                    }
+                }
+            }
+        }
+        return true;
+    }
+
+    /// as value is written in source code, strings are quoted
+    pub fn repr(&self, value: &Value) -> String {
+        match value {
+            Value::Str(s) => {format!("{:?}", s)}
+            other => {self.display(other).to_string()}
+        }
+    }
+
+    /// displays value together with objects it references
+    pub fn display<'a>(&'a self, value: &'a Value) -> DisplayValue<'a> {
+        DisplayValue{heap: self, value}
+    }
+
+    /// strings inside of collections are quoted, collections that contain themselves are shown as [...] or {...}.
+    /// nested collections are walked with explicit stack, so deep nesting does not overflow native stack
+    fn fmt_value(&self, f: &mut Formatter<'_>, value: &Value) -> fmt::Result {
+        if let Value::Str(s) = value {
+            return write!(f, "{}", s);
+        }
+        let mut steps = vec![FmtStep::Value(value)];
+        //collections being displayed around current step
+        let mut outer: HashSet<ObjRef> = HashSet::new();
+        while let Some(step) = steps.pop() {
+            match step {
+                FmtStep::Text(text) => {write!(f, "{}", text)?;}
+                FmtStep::Leave(obj) => {outer.remove(&obj);}
+                FmtStep::Key(MapKey::Int(i)) => {write!(f, "{}", i)?;}
+                FmtStep::Key(MapKey::BigInt(n)) => {write!(f, "{}", n)?;}
+                FmtStep::Key(MapKey::Str(s)) => {write!(f, "{:?}", s)?;}
+                FmtStep::Value(value) => {
+                    match value {
+                        Value::Int(i) => {write!(f, "{}", i)?;}
+                        Value::BigInt(n) => {write!(f, "{}", n)?;}
+                        Value::Float(n) => {write!(f, "{:?}", n)?;} //keeps .0 of whole numbers
+                        Value::Bool(b) => {write!(f, "{}", b)?;}
+                        Value::Str(s) => {write!(f, "{:?}", s)?;}
+                        Value::Closure(obj) => {write!(f, "<fn {}>", self.closure(*obj).function.name)?;}
+                        Value::List(obj) => {
+                            if !outer.insert(*obj) {
+                                write!(f, "[...]")?;
+                                continue;
+                            }
+                            write!(f, "[")?;
+                            //steps are taken from the end
+                            steps.push(FmtStep::Leave(*obj));
+                            steps.push(FmtStep::Text("]"));
+                            for (i, item) in self.list(*obj).iter().enumerate().rev() {
+                                steps.push(FmtStep::Value(item));
+                                if i>0 {
+                                    steps.push(FmtStep::Text(", "));
+                                }
+                            }
+                        }
+                        Value::Map(obj) => {
+                            if !outer.insert(*obj) {
+                                write!(f, "{{...}}")?;
+                                continue;
+                            }
+                            write!(f, "{{")?;
+                            steps.push(FmtStep::Leave(*obj));
+                            steps.push(FmtStep::Text("}"));
+                            let entries: Vec<(&MapKey, &Value)> = self.map(*obj).entries().collect();
+                            for (i, (key, value)) in entries.into_iter().enumerate().rev() {
+                                steps.push(FmtStep::Value(value));
+                                steps.push(FmtStep::Text(": "));
+                                steps.push(FmtStep::Key(key));
+                                if i>0 {
+                                    steps.push(FmtStep::Text(", "));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// values met by mark phase
+#[derive(Default)]
+struct ReachableValues {
+    /// strings and big integers shared by several values are counted once
+    counted: HashSet<*const u8>,
+    bytes: usize
+}
+
+impl ReachableValues {
+    fn visit(&mut self, value: &Value, gray: &mut Vec<ObjRef>) {
+        let address = match value {
+            Value::Str(s) => {s.as_ptr()}
+            Value::BigInt(n) => {Rc::as_ptr(n) as *const u8}
+            other => {
+                gray.extend(other.obj_ref());
+                return;
+            }
+        };
+        if self.counted.insert(address) {
+            self.bytes += value_size(value);
+        }
+    }
+}
+
+/// part of displayed value that is left to write
+enum FmtStep<'a> {
+    Value(&'a Value),
+    Key(&'a MapKey),
+    Text(&'static str),
+    /// end of collection, it may be displayed again outside of itself
+    Leave(ObjRef)
+}
+
+pub struct DisplayValue<'a> {
+    heap: &'a Heap,
+    value: &'a Value
+}
+
+impl Display for DisplayValue<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.heap.fmt_value(f, self.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Interpreter, VMOptions};
+
+    fn list(heap: &mut Heap, items: Vec<Value>) -> Value {
+        Value::List(heap.alloc(Object::List(items)))
+    }
+
+    /// interpreter that collects garbage on every allocation
+    fn eager_interpreter() -> Interpreter {
+        Interpreter::with_options(VMOptions{gc_threshold: 0, ..VMOptions::default()})
+    }
+
+    #[test]
+    fn unreachable_cycle_is_freed() {
+        let mut heap = Heap::new(0);
+        //elements are replaced in place like index assignment does, so sizes stay as accounted
+        let a = list(&mut heap, vec![Value::Int(0)]);
+        let b = list(&mut heap, vec![a.clone()]);
+        heap.list_mut(a.obj_ref().unwrap())[0] = b.clone();
+
+        heap.collect([a.clone()].iter(), std::iter::empty());
+        assert_eq!(heap.stats().live_objects, 2);
+
+        heap.collect(std::iter::empty(), std::iter::empty());
+        let stats = heap.stats();
+        assert_eq!(stats.live_objects, 0);
+        assert_eq!(stats.freed, 2);
+        assert_eq!(stats.bytes, 0);
+    }
+
+    #[test]
+    fn freed_slot_is_reused() {
+        let mut heap = Heap::new(0);
+        let old = heap.alloc(Object::List(vec![Value::Int(1)]));
+        heap.collect(std::iter::empty(), std::iter::empty());
+        let new = heap.alloc(Object::Map(Map::default()));
+        //handle kept from before collection now refers to a map, so handles must not outlive their objects
+        assert_eq!(old, new);
+        assert_eq!(heap.map(new).len(), 0);
+    }
+
+    #[test]
+    fn closed_upvalues_are_roots() {
+        let mut interpreter = eager_interpreter();
+        interpreter.eval("
+            fn make() { var xs = [1, 2, 3]; return fn () { return xs[1]; }; }
+            var f = make();
+            var garbage = 0;
+            for i in 0..100 { garbage = [i, [i]]; }
+            var result = f();
+        ").unwrap();
+        assert_eq!(interpreter.get_global::<i32>("result"), Some(2));
+        let stats = interpreter.heap_stats();
+        assert!(stats.collections>0);
+        assert!(stats.freed>0);
+    }
+
+    #[test]
+    fn cyclic_values_compare() {
+        let mut interpreter = eager_interpreter();
+        interpreter.eval("
+            var xs = [1]; xs[0] = xs;
+            var ys = [1]; ys[0] = ys;
+            var m = {\"a\": 1}; m[\"self\"] = m;
+            var n = {\"a\": 2}; n[\"self\"] = n;
+            var same = xs == xs;
+            var similar = xs == ys;
+            var inside = xs in [1, ys];
+            var maps = m == n;
+        ").unwrap();
+        assert_eq!(interpreter.get_global::<bool>("same"), Some(true));
+        assert_eq!(interpreter.get_global::<bool>("similar"), Some(true));
+        assert_eq!(interpreter.get_global::<bool>("inside"), Some(true));
+        assert_eq!(interpreter.get_global::<bool>("maps"), Some(false));
+    }
+
+    #[test]
+    fn deep_nesting_does_not_overflow() {
+        let depth = 200000;
+        let mut heap = Heap::new(0);
+        let mut a = list(&mut heap, vec![]);
+        let mut b = list(&mut heap, vec![]);
+        for _ in 0..depth {
+            a = list(&mut heap, vec![a]);
+            b = list(&mut heap, vec![b]);
+        }
+        assert!(heap.values_equal(&a, &a));
+        assert!(heap.values_equal(&a, &b));
+        let shown = heap.display(&a).to_string();
+        assert_eq!(shown.len(), 2*(depth+1));
+        assert!(shown.starts_with("[[[") && shown.ends_with("]]]"));
+    }
+
+    #[test]
+    fn display_marks_cycles() {
+        let mut heap = Heap::new(0);
+        let a = list(&mut heap, vec![Value::Int(1), Value::from("s"), Value::Int(0)]);
+        heap.list_mut(a.obj_ref().unwrap())[2] = a.clone();
+        assert_eq!(heap.display(&a).to_string(), "[1, \"s\", [...]]");
+        assert_eq!(heap.display(&Value::from("top")).to_string(), "top");
+    }
+}
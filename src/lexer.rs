@@ -1,7 +1,14 @@
 use std::fmt::{Display, Formatter};
 use std::fmt;
 use std::option::Option::Some;
-#[derive(Copy, Clone)]
+use std::iter::Peekable;
+use std::str::CharIndices;
+use crate::error::{LexicalError, LexicalErrorKind};
+use crate::bigint::BigInt;
+use crate::vm::IntegerMode;
+use std::rc::Rc;
+
+#[derive(Copy, Clone, Debug)]
 pub struct TokenIndex {
     pub index: usize,
     pub line_number:usize
@@ -15,15 +22,65 @@ impl Display for TokenIndex{
 
 pub const MOCK_IDX: TokenIndex = TokenIndex{index:0,line_number:0};
 
+/// region of source text, end is exclusive. both ends are on the same line for single tokens
+#[derive(Copy, Clone, Debug)]
+pub struct Span {
+    pub start: TokenIndex,
+    pub end: TokenIndex
+}
+
+impl Span {
+    pub fn at(start: TokenIndex, width: usize) -> Span {
+        Span{start, end: TokenIndex{index: start.index+width, line_number: start.line_number}}
+    }
+
+    /// span covering both self and other, other is expected to come later in source
+    pub fn join(self, other: Span) -> Span {
+        Span{start: self.start, end: other.end}
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
 
 pub enum Token{
-    Op(char, TokenIndex),
-    Number(i32, TokenIndex),
+    Op(&'static str, TokenIndex),
+    /// value and length of literal in source, which differs from length of value when it has leading zeros
+    Number(i32, usize, TokenIndex),
+    /// integer literal that does not fit into i32 and length of it in source
+    BigNumber(Rc<BigInt>, usize, TokenIndex),
+    /// value and length of literal in source
+    Float(f64, usize, TokenIndex),
+    Bool(bool, TokenIndex),
+    /// contents with escapes processed and length of literal in source, quotes included
+    Str(String, usize, TokenIndex),
     LBracket(TokenIndex),
     RBracket(TokenIndex),
+    LBrace(TokenIndex),
+    RBrace(TokenIndex),
+    LSquare(TokenIndex),
+    RSquare(TokenIndex),
     Print(TokenIndex),
     Var(TokenIndex),
+    If(TokenIndex),
+    Else(TokenIndex),
+    While(TokenIndex),
+    For(TokenIndex),
+    In(TokenIndex),
+    Break(TokenIndex),
+    Continue(TokenIndex),
+    Fn(TokenIndex),
+    Return(TokenIndex),
+    Comma(TokenIndex),
+    Colon(TokenIndex),
+    DotDot(TokenIndex),
     Equals(TokenIndex),
+    /// one of += -= *= /= %=, holds binary operator
+    CompoundAssign(&'static str, TokenIndex),
     Identifier(String, TokenIndex),
     Semicolon(TokenIndex),
     EOF(TokenIndex)
@@ -33,27 +90,122 @@ impl Token {
     pub fn get_pos(&self) -> TokenIndex {
         *match self {
             Token::Op(_, r) => {r}
-            Token::Number(_, r) => {r}
+            Token::Number(_, _, r) => {r}
+            Token::BigNumber(_, _, r) => {r}
+            Token::Float(_, _, r) => {r}
+            Token::Bool(_, r) => {r}
+            Token::Str(_, _, r) => {r}
             Token::LBracket(r) => {r}
             Token::RBracket(r) => {r}
+            Token::LBrace(r) => {r}
+            Token::RBrace(r) => {r}
+            Token::LSquare(r) => {r}
+            Token::RSquare(r) => {r}
             Token::Print(r) => {r}
             Token::Var(r) => {r}
+            Token::If(r) => {r}
+            Token::Else(r) => {r}
+            Token::While(r) => {r}
+            Token::For(r) => {r}
+            Token::In(r) => {r}
+            Token::Break(r) => {r}
+            Token::Continue(r) => {r}
+            Token::Fn(r) => {r}
+            Token::Return(r) => {r}
+            Token::Comma(r) => {r}
+            Token::Colon(r) => {r}
+            Token::DotDot(r) => {r}
             Token::Equals(r) => {r}
+            Token::CompoundAssign(_, r) => {r}
             Token::Identifier(_, r) => {r}
             Token::Semicolon(r) => {r}
             Token::EOF(r) => {r}
         }
     }
 
+    /// length of token in source text
+    pub fn width(&self) -> usize {
+        match self {
+            Token::Op(op, _) => {op.len()}
+            Token::Number(_, width, _) => {*width}
+            Token::BigNumber(_, width, _) => {*width}
+            Token::Float(_, width, _) => {*width}
+            Token::Bool(b, _) => {b.to_string().len()}
+            Token::Str(_, width, _) => {*width}
+            Token::LBracket(_) | Token::RBracket(_) => {1}
+            Token::LBrace(_) | Token::RBrace(_) => {1}
+            Token::LSquare(_) | Token::RSquare(_) => {1}
+            Token::Print(_) => {"print".len()}
+            Token::Var(_) => {"var".len()}
+            Token::If(_) => {"if".len()}
+            Token::Else(_) => {"else".len()}
+            Token::While(_) => {"while".len()}
+            Token::For(_) => {"for".len()}
+            Token::In(_) => {"in".len()}
+            Token::Break(_) => {"break".len()}
+            Token::Continue(_) => {"continue".len()}
+            Token::Fn(_) => {"fn".len()}
+            Token::Return(_) => {"return".len()}
+            Token::Comma(_) => {1}
+            Token::Colon(_) => {1}
+            Token::DotDot(_) => {2}
+            Token::Equals(_) => {1}
+            Token::CompoundAssign(op, _) => {op.len()+1}
+            Token::Identifier(name, _) => {name.len()}
+            Token::Semicolon(_) => {1}
+            Token::EOF(_) => {0}
+        }
+    }
+
+    pub fn get_span(&self) -> Span {
+        Span::at(self.get_pos(), self.width())
+    }
+
+    /// human-readable description of token for error messages, without position
+    pub fn describe(&self) -> String {
+        match self {
+            Token::Op(op, _) => {format!("operator {}", op)}
+            Token::CompoundAssign(op, _) => {format!("{}=", op)}
+            Token::Number(n, _, _) => {format!("number {}", n)}
+            Token::BigNumber(n, _, _) => {format!("number {}", n)}
+            Token::Float(n, _, _) => {format!("number {:?}", n)}
+            Token::Bool(b, _) => {format!("{}", b)}
+            Token::Str(string, _, _) => {format!("string {:?}", string)}
+            Token::Identifier(name, _) => {format!("identifier {}", name)}
+            Token::EOF(_) => {"end of input".to_string()}
+            _ => {self.get_token_type_name()}
+        }
+    }
+
     pub fn get_token_type_name(&self) -> String {
         match self {
-            Token::Op(..) => {"Binary operator"}
-            Token::Number(..) => {"Number"}
+            Token::Op(..) => {"operator"}
+            Token::Number(..) | Token::BigNumber(..) => {"Number"}
+            Token::Float(..) => {"Float"}
+            Token::Bool(..) => {"boolean"}
+            Token::Str(..) => {"string"}
             Token::LBracket(_) => {"("}
             Token::RBracket(_) => {")"}
+            Token::LBrace(_) => {"{"}
+            Token::RBrace(_) => {"}"}
+            Token::LSquare(_) => {"["}
+            Token::RSquare(_) => {"]"}
             Token::Print(_) => {"print keyword"}
             Token::Var(_) => {"var keyword"}
+            Token::If(_) => {"if keyword"}
+            Token::Else(_) => {"else keyword"}
+            Token::While(_) => {"while keyword"}
+            Token::For(_) => {"for keyword"}
+            Token::In(_) => {"in keyword"}
+            Token::Break(_) => {"break keyword"}
+            Token::Continue(_) => {"continue keyword"}
+            Token::Fn(_) => {"fn keyword"}
+            Token::Return(_) => {"return keyword"}
+            Token::Comma(_) => {"comma"}
+            Token::Colon(_) => {"colon"}
+            Token::DotDot(_) => {".."}
             Token::Equals(_) => {"equals"}
+            Token::CompoundAssign(..) => {"compound assignment"}
             Token::Identifier(..) => {"identifier"}
             Token::Semicolon(_) => {"semicolon"}
             Token::EOF(_) => {"EOF"}
@@ -64,12 +216,33 @@ impl Token {
 impl Display for Token {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "{}", match self {
-            Token::Op(c, r) => {format!("<operator {} [{},{}]>", c, r.line_number, r.index)}
-            Token::Number(n, r) => {format!("<Number {} [{},{}]>", n, r.line_number, r.index)}
+            Token::Op(op, r) => {format!("<operator {} [{},{}]>", op, r.line_number, r.index)}
+            Token::Number(n, _, r) => {format!("<Number {} [{},{}]>", n, r.line_number, r.index)}
+            Token::BigNumber(n, _, r) => {format!("<Number {} [{},{}]>", n, r.line_number, r.index)}
+            Token::Float(n, _, r) => {format!("<Float {:?} [{},{}]>", n, r.line_number, r.index)}
+            Token::Bool(b, r) => {format!("<Bool {} [{},{}]>", b, r.line_number, r.index)}
+            Token::Str(string, _, r) => {format!("<String {:?} [{},{}]>", string, r.line_number, r.index)}
             Token::LBracket(r) => {format!("<( [{}, {}]>", r.line_number, r.index)}
             Token::RBracket(r) => {format!("<) [{}, {}]>", r.line_number, r.index)}
+            Token::LBrace(r) => {format!("<{{ [{}, {}]>", r.line_number, r.index)}
+            Token::RBrace(r) => {format!("<}} [{}, {}]>", r.line_number, r.index)}
+            Token::LSquare(r) => {format!("<[ [{}, {}]>", r.line_number, r.index)}
+            Token::RSquare(r) => {format!("<] [{}, {}]>", r.line_number, r.index)}
+            Token::If(r) => {format!("<if [{},{}]>", r.line_number, r.index)}
+            Token::Else(r) => {format!("<else [{},{}]>", r.line_number, r.index)}
+            Token::While(r) => {format!("<while [{},{}]>", r.line_number, r.index)}
+            Token::For(r) => {format!("<for [{},{}]>", r.line_number, r.index)}
+            Token::In(r) => {format!("<in [{},{}]>", r.line_number, r.index)}
+            Token::Break(r) => {format!("<break [{},{}]>", r.line_number, r.index)}
+            Token::Continue(r) => {format!("<continue [{},{}]>", r.line_number, r.index)}
+            Token::Fn(r) => {format!("<fn [{},{}]>", r.line_number, r.index)}
+            Token::Return(r) => {format!("<return [{},{}]>", r.line_number, r.index)}
+            Token::Comma(r) => {format!("<, [{},{}]>", r.line_number, r.index)}
+            Token::Colon(r) => {format!("<: [{},{}]>", r.line_number, r.index)}
+            Token::DotDot(r) => {format!("<.. [{},{}]>", r.line_number, r.index)}
             Token::Print(r) => {format!("<print [{}, {}]>", r.line_number, r.index)}
             Token::Equals(r) => {format!("<= [{}, {}]>", r.line_number, r.index)}
+            Token::CompoundAssign(op, r) => {format!("<{}= [{},{}]>", op, r.line_number, r.index)}
             Token::Identifier(name, r) => {format!("<variable {} [{},{}]>", name, r.line_number, r.index)}
             Token::Semicolon(r) => {format!("<; [{},{}]>", r.line_number, r.index)}
             Token::Var(r) => {format!("<var [{},{}]>", r.line_number, r.index)}
@@ -78,7 +251,8 @@ impl Display for Token {
     }
 }
 
-pub fn tokenize(input:&str) -> Result<Vec<Token>, String>{
+/// integer literals that do not fit into i32 are only accepted in big integer mode
+pub fn tokenize(input:&str, integer_mode:IntegerMode) -> Result<Vec<Token>, LexicalError>{
 
     use Token::*;
 
@@ -99,13 +273,53 @@ pub fn tokenize(input:&str) -> Result<Vec<Token>, String>{
     }
 
 
+    fn take_digits(iterator: &mut Peekable<CharIndices>, num: &mut String) {
+        while let Some(c) = iterator.peek().map(|pair| pair.1) {
+            if !isnum(c) {break;}
+            num.push(c);
+            iterator.next();
+        }
+    }
+
+    /// consumes next character if it is c
+    fn next_is(iterator: &mut Peekable<CharIndices>, c:char) -> bool {
+        if let Some((_, next)) = iterator.peek() {
+            if *next==c {
+                iterator.next();
+                return true;
+            }
+        }
+        return false;
+    }
+
+
     let mut iterator = input.char_indices().peekable();
     let mut res:Vec<Token> = Vec::new();
     while let Some(pair) = iterator.peek() {
-        let c = (*pair).1;
-        let absolute_idx = (*pair).0;
+        let c = pair.1;
+        let absolute_idx = pair.0;
         match c {
-            '+' | '-'| '*' => {res.push(Op(c, current_index(pair.0, line_start, line_number))); iterator.next();}
+            '+' | '-' | '*' | '%' => {
+                let idx = current_index(absolute_idx, line_start, line_number);
+                iterator.next();
+                let op = match c {
+                    '+' => {"+"}
+                    '-' => {"-"}
+                    '%' => {"%"}
+                    _ if next_is(&mut iterator, '*') => {"**"}
+                    _ => {"*"}
+                };
+                //** has no compound form
+                if op!="**" && next_is(&mut iterator, '=') {
+                    res.push(CompoundAssign(op, idx));
+                }else{
+                    res.push(Op(op, idx));
+                }
+            }
+            '&' => {res.push(Op("&", current_index(absolute_idx, line_start, line_number))); iterator.next();}
+            '|' => {res.push(Op("|", current_index(absolute_idx, line_start, line_number))); iterator.next();}
+            '^' => {res.push(Op("^", current_index(absolute_idx, line_start, line_number))); iterator.next();}
+            '~' => {res.push(Op("~", current_index(absolute_idx, line_start, line_number))); iterator.next();}
 
             '/' => {
                 //division or comment
@@ -113,7 +327,7 @@ pub fn tokenize(input:&str) -> Result<Vec<Token>, String>{
                 match iterator.peek() {
                     Some((_, '/')) => {
                         //comment
-                        while let Some(pair) = iterator.next() { //skip until EOL
+                        for pair in iterator.by_ref() { //skip until EOL
                             if pair.1=='\n' {update_newline(pair.0, &mut line_start, &mut line_number);break;}
                         }
                     }
@@ -128,16 +342,60 @@ pub fn tokenize(input:&str) -> Result<Vec<Token>, String>{
                             if p1.1=='*' && p2.1=='/' {ended_flag=true; break;}
                         }
 
-                        if !ended_flag {return Err(format!("lexer error: unterminated multiline comment starting at {}", start))}
+                        if !ended_flag {
+                            return Err(LexicalError{kind: LexicalErrorKind::UnterminatedComment, span: Span::at(start, 2)});
+                        }
                         iterator.next(); //consume /
                     }
 
-                    _ => {res.push(Op('/', current_index(absolute_idx, line_start, line_number)))}
+                    Some((_, '=')) => {
+                        res.push(CompoundAssign("/", current_index(absolute_idx, line_start, line_number)));
+                        iterator.next();
+                    }
+
+                    _ => {res.push(Op("/", current_index(absolute_idx, line_start, line_number)))}
                 }
             }
 
             '(' => {res.push(LBracket(current_index(absolute_idx, line_start, line_number))); iterator.next();}
             ')' => {res.push(RBracket(current_index(absolute_idx, line_start, line_number))); iterator.next();}
+            '{' => {res.push(LBrace(current_index(absolute_idx, line_start, line_number))); iterator.next();}
+            '}' => {res.push(RBrace(current_index(absolute_idx, line_start, line_number))); iterator.next();}
+            '[' => {res.push(LSquare(current_index(absolute_idx, line_start, line_number))); iterator.next();}
+            ']' => {res.push(RSquare(current_index(absolute_idx, line_start, line_number))); iterator.next();}
+
+            '"' => {
+                let start_idx = current_index(absolute_idx, line_start, line_number);
+                iterator.next();
+                let mut string = String::new();
+                let width = loop {
+                    match iterator.next() {
+                        Some((idx, '"')) => {break idx+1-absolute_idx;}
+                        Some((_, '\\')) => {
+                            let escaped = match iterator.next() {
+                                Some((_, 'n')) => {'\n'}
+                                Some((_, 't')) => {'\t'}
+                                Some((_, 'r')) => {'\r'}
+                                Some((_, '0')) => {'\0'}
+                                Some((_, '\\')) => {'\\'}
+                                Some((_, '"')) => {'"'}
+                                Some((idx, other)) if other!='\n' => {
+                                    let span = Span::at(current_index(idx-1, line_start, line_number), 1+other.len_utf8());
+                                    return Err(LexicalError{kind: LexicalErrorKind::UnknownEscape(other), span});
+                                }
+                                _ => {return Err(LexicalError{kind: LexicalErrorKind::UnterminatedString, span: Span::at(start_idx, 1)});}
+                            };
+                            string.push(escaped);
+                        }
+                        //strings can't span several lines
+                        Some((_, '\n')) | None => {
+                            return Err(LexicalError{kind: LexicalErrorKind::UnterminatedString, span: Span::at(start_idx, 1)});
+                        }
+                        Some((_, c)) => {string.push(c);}
+                    }
+                };
+                res.push(Str(string, width, start_idx));
+            }
 
             _ if isnum(c) => {
                 let start_idx = current_index(absolute_idx, line_start, line_number);
@@ -145,17 +403,49 @@ pub fn tokenize(input:&str) -> Result<Vec<Token>, String>{
                 num.push(c);
                 iterator.next();
 
-                while let Some(c) = iterator.peek().map(|pair| (*pair).1)  {
+                take_digits(&mut iterator, &mut num);
 
-                    if isnum(c) {
-                        num.push(c);
+                let mut is_float = false;
+                //'.' must be followed by digit, so that ranges like 1..10 are not taken for floats
+                if let Some(&(idx, '.')) = iterator.peek() {
+                    if input[idx+1..].starts_with(isnum) {
+                        is_float = true;
+                        num.push('.');
                         iterator.next();
-                    }else{
-                        break;
+                        take_digits(&mut iterator, &mut num);
+                    }
+                }
+                if let Some(&(idx, e)) = iterator.peek() {
+                    let exponent = &input[idx+1..];
+                    let unsigned = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+                    if (e=='e' || e=='E') && unsigned.starts_with(isnum) {
+                        is_float = true;
+                        num.push('e');
+                        iterator.next();
+                        if unsigned.len()!=exponent.len() {
+                            num.push(iterator.next().unwrap().1); //sign
+                        }
+                        take_digits(&mut iterator, &mut num);
                     }
                 }
 
-                res.push(Number(str::parse::<i32>(&*num).unwrap(), start_idx));
+                if is_float {
+                    let end = iterator.peek().map(|pair| pair.0).unwrap_or(input.len());
+                    res.push(Float(str::parse::<f64>(&num).unwrap(), end-absolute_idx, start_idx));
+                }else{
+                    //in big integer mode literals of any length are accepted, those that don't fit into i32 become big integers
+                    match str::parse::<i32>(&num) {
+                        Ok(n) => {res.push(Number(n, num.len(), start_idx));}
+                        Err(_) if integer_mode==IntegerMode::Fixed => {
+                            let span = Span::at(start_idx, num.len());
+                            return Err(LexicalError{kind: LexicalErrorKind::IntegerLiteralOverflow, span});
+                        }
+                        Err(_) => {
+                            let n = BigInt::from_decimal(&num).unwrap();
+                            res.push(BigNumber(Rc::new(n), num.len(), start_idx));
+                        }
+                    }
+                }
 
             }
 
@@ -165,7 +455,7 @@ pub fn tokenize(input:&str) -> Result<Vec<Token>, String>{
                 token.push(c);
                 iterator.next();
 
-                while let Some(c) = iterator.peek().map(|pair| (*pair).1){
+                while let Some(c) = iterator.peek().map(|pair| pair.1){
                     if isalphanum(c) {
                         token.push(c);
                         iterator.next();
@@ -181,6 +471,21 @@ pub fn tokenize(input:&str) -> Result<Vec<Token>, String>{
                     "var" => {
                         res.push(Var(start_idx));
                     }
+                    "if" => {res.push(If(start_idx));}
+                    "else" => {res.push(Else(start_idx));}
+                    "while" => {res.push(While(start_idx));}
+                    "for" => {res.push(For(start_idx));}
+                    "in" => {res.push(In(start_idx));}
+                    "break" => {res.push(Break(start_idx));}
+                    "continue" => {res.push(Continue(start_idx));}
+                    "fn" => {res.push(Fn(start_idx));}
+                    "return" => {res.push(Return(start_idx));}
+                    "true" => {res.push(Bool(true, start_idx));}
+                    "false" => {res.push(Bool(false, start_idx));}
+                    "and" => {res.push(Op("and", start_idx));}
+                    "or" => {res.push(Op("or", start_idx));}
+                    "not" => {res.push(Op("not", start_idx));}
+                    "div" => {res.push(Op("div", start_idx));}
 
                     _ => {
                         res.push(Identifier(token, start_idx));
@@ -189,8 +494,36 @@ pub fn tokenize(input:&str) -> Result<Vec<Token>, String>{
 
             }
 
-            '=' => {res.push(Equals(current_index(absolute_idx, line_start, line_number))); iterator.next();}
+            '=' => {
+                let idx = current_index(absolute_idx, line_start, line_number);
+                iterator.next();
+                if next_is(&mut iterator, '=') {res.push(Op("==", idx));} else {res.push(Equals(idx));}
+            }
+            '<' => {
+                let idx = current_index(absolute_idx, line_start, line_number);
+                iterator.next();
+                let op = if next_is(&mut iterator, '<') {"<<"} else if next_is(&mut iterator, '=') {"<="} else {"<"};
+                res.push(Op(op, idx));
+            }
+            '>' => {
+                let idx = current_index(absolute_idx, line_start, line_number);
+                iterator.next();
+                let op = if next_is(&mut iterator, '>') {">>"} else if next_is(&mut iterator, '=') {">="} else {">"};
+                res.push(Op(op, idx));
+            }
+            '.' if input[absolute_idx+1..].starts_with('.') => {
+                res.push(DotDot(current_index(absolute_idx, line_start, line_number)));
+                iterator.next();
+                iterator.next();
+            }
+            '!' if input[absolute_idx+1..].starts_with('=') => {
+                res.push(Op("!=", current_index(absolute_idx, line_start, line_number)));
+                iterator.next();
+                iterator.next();
+            }
             ';' => {res.push(Semicolon(current_index(absolute_idx, line_start, line_number))); iterator.next();}
+            ',' => {res.push(Comma(current_index(absolute_idx, line_start, line_number))); iterator.next();}
+            ':' => {res.push(Colon(current_index(absolute_idx, line_start, line_number))); iterator.next();}
 
             _ if c==' '|| c=='\t'||c=='\r' => {iterator.next();}
             _ if c=='\n' => {
@@ -198,7 +531,10 @@ pub fn tokenize(input:&str) -> Result<Vec<Token>, String>{
                 iterator.next();
             }
 
-            _ => {return Err(format!("unknown character {} at {}", c, (*pair).0))}
+            _ => {
+                let span = Span::at(current_index(absolute_idx, line_start, line_number), c.len_utf8());
+                return Err(LexicalError{kind: LexicalErrorKind::UnknownCharacter(c), span});
+            }
         }
     }
     res.push(Token::EOF(current_index(input.len(), line_start, line_number)));
@@ -206,10 +542,10 @@ pub fn tokenize(input:&str) -> Result<Vec<Token>, String>{
 }
 
 fn isalpha(c:char) -> bool {
-    return ('a'..='z').contains(&c) || ('A'..='Z').contains(&c) || c=='_';
+    return c.is_ascii_alphabetic() || c=='_';
 }
 fn isnum(c:char) -> bool {
-    return ('0'..='9').contains(&c);
+    return c.is_ascii_digit();
 }
 
 fn isalphanum(c:char) -> bool {
@@ -0,0 +1,210 @@
+#![allow(clippy::needless_return, clippy::upper_case_acronyms)]
+
+/*
+interpreter of a small scripting language: source is tokenized, parsed into ast,
+compiled into bytecode and executed by a stack VM.
+Interpreter runs the whole pipeline, stages are also usable on their own through their modules.
+ */
+
+pub mod error;
+pub mod bigint;
+pub mod diagnostics;
+pub mod lexer;
+pub mod parser;
+pub mod lisp_print;
+pub mod compiler;
+pub mod vm;
+pub mod heap;
+
+pub use crate::error::Error;
+pub use crate::vm::{VMOptions, IntegerMode, OverflowPolicy, Value, Limits};
+pub use crate::heap::HeapStats;
+
+use crate::lexer::{tokenize, Token};
+use crate::compiler::Compiler;
+use crate::parser::{Expr, ExprType};
+use crate::vm::VM;
+use crate::error::RuntimeError;
+use std::path::Path;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+/// turns top level expression statements into prints, so that REPL shows their values.
+/// assignments are not echoed
+fn echo_expressions(program:&mut Expr) {
+    for stmt in program.children.iter_mut() {
+        if let ExprType::ExprStmt = stmt.expr_type {
+            if !matches!(stmt.children[0].expr_type, ExprType::Assign(_) | ExprType::CompoundAssign(..)
+                | ExprType::IndexAssign | ExprType::CompoundIndexAssign(_)) {
+                stmt.expr_type = ExprType::PrintStmt;
+            }
+        }
+    }
+}
+
+/// session of compiler and VM. variables declared by one piece of code stay visible to the next ones.
+/// code that fails to tokenize, parse or compile changes nothing, runtime errors keep
+/// declarations and assignments made before the error
+pub struct Interpreter {
+    compiler:Compiler,
+    vm:VM,
+    /// receives tokens, ast and bytecode of evaluated code when set
+    trace:Option<Box<dyn Write>>,
+    /// applied to every evaluation separately
+    limits:Limits,
+    options:VMOptions
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter::with_options(VMOptions::default())
+    }
+
+    pub fn with_options(options:VMOptions) -> Interpreter {
+        Interpreter{compiler: Compiler::new(), vm: VM::with_options(options), trace: None, limits: Limits::default(), options}
+    }
+
+    /// output of print statements goes to out, stdout is used by default
+    pub fn set_output(&mut self, out:Box<dyn Write>) {
+        self.vm.set_output(out);
+    }
+
+    /// debug tracing of compilation stages, disabled by default
+    pub fn set_trace(&mut self, trace:Option<Box<dyn Write>>) {
+        self.trace = trace;
+    }
+
+    pub fn options(&self) -> VMOptions {
+        self.options
+    }
+
+    /// runs program or part of it
+    pub fn eval(&mut self, source:&str) -> Result<(), Error> {
+        self.run(source, false)
+    }
+
+    /// runs line typed into REPL: values of top level expression statements are printed
+    pub fn eval_line(&mut self, source:&str) -> Result<(), Error> {
+        self.run(source, true)
+    }
+
+    pub fn run_file<P: AsRef<Path>>(&mut self, path:P) -> Result<(), Error> {
+        let content = fs::read_to_string(path)?;
+        self.eval(&content)
+    }
+
+    /// makes host function callable from scripts as name(args). arity is checked when calling code is compiled
+    pub fn register_native<F>(&mut self, name:&str, arity:usize, function:F)
+        where F: Fn(&[Value]) -> Result<Value, String> + 'static {
+        let index = self.vm.register_native(name, arity, function);
+        self.compiler.declare_native(name, index, arity);
+    }
+
+    /// value of top-level variable converted to T, None if variable is not declared or has other type.
+    /// lists, maps and functions stay inside of scripts and are never returned
+    pub fn get_global<T: TryFrom<Value>>(&self, name:&str) -> Option<T> {
+        let idx = self.compiler.global_index(name)?;
+        return self.vm.global(idx).ok().and_then(|value| T::try_from(value).ok());
+    }
+
+    /// assigns top-level variable, declaring it if needed. scripts evaluated later see it as if they declared it.
+    /// only ints, floats, bools and strings are accepted
+    pub fn set_global<T: Into<Value>>(&mut self, name:&str, value:T) -> Result<(), Error> {
+        let value = value.into();
+        //variable is declared only if value is accepted
+        value.check_host_value().map_err(RuntimeError::from)?;
+        let idx = self.compiler.define_global(name);
+        self.vm.set_global(idx, value).map_err(RuntimeError::from)?;
+        Ok(())
+    }
+
+    pub fn set_limits(&mut self, limits:Limits) {
+        self.limits = limits;
+    }
+
+    /// flag that stops running code when set, may be shared with other threads.
+    /// it is cleared when evaluation starts, setting it while nothing runs has no effect
+    pub fn interrupt_handle(&self) -> Arc<AtomicBool> {
+        self.vm.interrupt_handle()
+    }
+
+    pub fn heap_stats(&self) -> HeapStats {
+        self.vm.heap_stats()
+    }
+
+    fn run(&mut self, source:&str, echo:bool) -> Result<(), Error> {
+        //tracing is best effort, failure to write it does not stop evaluation
+        let tokens: Vec<Token> = tokenize(source, self.options.integer_mode)?;
+
+        if let Some(trace) = self.trace.as_mut() {
+            let _ = writeln!(trace, "{}",
+                     tokens
+                         .iter()
+                         .map(|x| format!("{}", x))
+                         .collect::<Vec<String>>()
+                         .join(", ")
+            );
+        }
+
+        let mut ast = parser::parse(&tokens)?;
+        if echo {
+            echo_expressions(&mut ast);
+        }
+
+        if let Some(trace) = self.trace.as_mut() {
+            let _ = lisp_print::visit(&ast, trace);
+        }
+
+        let code_chunk = self.compiler.continue_compile(&ast)?;
+
+        if let Some(trace) = self.trace.as_mut() {
+            let _ = code_chunk.dump(trace);
+        }
+
+        let result = self.vm.run_limited(&code_chunk, &self.limits);
+
+        if let Some(trace) = self.trace.as_mut() {
+            let _ = writeln!(trace, "VM: stack_size={}, stack.len()={}", self.vm.initial_stack_size, self.vm.stack.len());
+        }
+
+        result?;
+        Ok(())
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heap_values_do_not_leave_vm() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_native("id", 1, |args| Ok(args[0].clone()));
+        interpreter.eval("var xs = [1]; var m = {1: 2}; fn f() { return 1; } var n = id(2);").unwrap();
+        assert!(interpreter.get_global::<Value>("xs").is_none());
+        assert!(interpreter.get_global::<Value>("m").is_none());
+        assert!(interpreter.get_global::<Value>("f").is_none());
+        assert_eq!(interpreter.get_global::<i32>("n"), Some(2));
+        assert!(interpreter.eval("id(xs);").is_err());
+    }
+
+    #[test]
+    fn set_global_accepts_only_scalars() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_global("x", 5).unwrap();
+        interpreter.set_global("s", "text").unwrap();
+        interpreter.eval("var y = x * 2; var t = s + \"!\";").unwrap();
+        assert_eq!(interpreter.get_global::<i32>("y"), Some(10));
+        assert_eq!(interpreter.get_global::<String>("t"), Some("text!".to_string()));
+        assert_eq!(interpreter.get_global::<bool>("y"), None);
+    }
+}
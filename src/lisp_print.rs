@@ -1,44 +1,165 @@
 use crate::parser::{ExprType, Expr};
+use std::io::{self, Write};
 
-pub fn visit(program:&Expr) {
-    _visit(program);
-}
-
-fn _visit(item: &Expr){
+/// writes ast in lisp-like form, statements of program on separate lines
+pub fn visit(item: &Expr, out: &mut dyn Write) -> io::Result<()> {
     match &item.expr_type {
-        ExprType::Op(c) => {
-            print!("({} ", c);
-            visit(&item.children[0]);
-            print!(" ");
-            visit(&item.children[1]);
-            print!(")");
-        }
-        ExprType::Literal(i) => {print!("{}", i)}
-        ExprType::Variable(name) => {print!("{}", name)}
-        ExprType::PrintStmt => { print!("(print ");
-            visit(&item.children[0]);
-            print!(")");
-        }
-        ExprType::AssignStmt(name) => {
-            print!("(= {} ", name);
-            visit(&item.children[0]);
-            print!(")");
+        ExprType::Op(op) | ExprType::Logical(op) => {
+            write!(out, "({} ", op)?;
+            visit(&item.children[0], out)?;
+            write!(out, " ")?;
+            visit(&item.children[1], out)?;
+            write!(out, ")")?;
+        }
+        ExprType::Unary(op) => {
+            write!(out, "({} ", op)?;
+            visit(&item.children[0], out)?;
+            write!(out, ")")?;
+        }
+        ExprType::Literal(i) => {write!(out, "{}", i)?}
+        ExprType::BigLiteral(i) => {write!(out, "{}", i)?}
+        ExprType::Float(n) => {write!(out, "{:?}", n)?}
+        ExprType::Bool(b) => {write!(out, "{}", b)?}
+        ExprType::Str(string) => {write!(out, "{:?}", string)?}
+        ExprType::Variable(name) => {write!(out, "{}", name)?}
+        ExprType::PrintStmt => { write!(out, "(print ")?;
+            visit(&item.children[0], out)?;
+            write!(out, ")")?;
+        }
+        ExprType::Assign(name) => {
+            write!(out, "(= {} ", name)?;
+            visit(&item.children[0], out)?;
+            write!(out, ")")?;
+        }
+        ExprType::CompoundAssign(name, op) => {
+            write!(out, "({}= {} ", op, name)?;
+            visit(&item.children[0], out)?;
+            write!(out, ")")?;
+        }
+        ExprType::List => {
+            write!(out, "(list")?;
+            for child in &item.children {
+                write!(out, " ")?;
+                visit(child, out)?;
+            }
+            write!(out, ")")?;
+        }
+        ExprType::Map => {
+            write!(out, "(map")?;
+            for child in &item.children {
+                write!(out, " ")?;
+                visit(child, out)?;
+            }
+            write!(out, ")")?;
+        }
+        ExprType::Index => {
+            write!(out, "(index ")?;
+            visit(&item.children[0], out)?;
+            write!(out, " ")?;
+            visit(&item.children[1], out)?;
+            write!(out, ")")?;
+        }
+        ExprType::IndexAssign | ExprType::CompoundIndexAssign(_) => {
+            let op = match &item.expr_type {
+                ExprType::CompoundIndexAssign(op) => {format!("{}=", op)}
+                _ => {"=".to_string()}
+            };
+            write!(out, "({} (index ", op)?;
+            visit(&item.children[0], out)?;
+            write!(out, " ")?;
+            visit(&item.children[1], out)?;
+            write!(out, ") ")?;
+            visit(&item.children[2], out)?;
+            write!(out, ")")?;
+        }
+        ExprType::ExprStmt => {
+            write!(out, "(expr ")?;
+            visit(&item.children[0], out)?;
+            write!(out, ")")?;
         }
         ExprType::Program => {
             for stmt in &item.children{
-                visit(stmt);
-                println!();
+                visit(stmt, out)?;
+                writeln!(out)?;
+            }
+        }
+        ExprType::Block => {
+            write!(out, "(block")?;
+            for stmt in &item.children{
+                write!(out, " ")?;
+                visit(stmt, out)?;
+            }
+            write!(out, ")")?;
+        }
+        ExprType::IfStmt => {
+            write!(out, "(if")?;
+            for child in &item.children{
+                write!(out, " ")?;
+                visit(child, out)?;
+            }
+            write!(out, ")")?;
+        }
+        ExprType::WhileStmt => {
+            write!(out, "(while ")?;
+            visit(&item.children[0], out)?;
+            write!(out, " ")?;
+            visit(&item.children[1], out)?;
+            write!(out, ")")?;
+        }
+        ExprType::ForStmt(name) => {
+            write!(out, "(for {} ", name)?;
+            visit(&item.children[0], out)?;
+            write!(out, " ")?;
+            visit(&item.children[1], out)?;
+            write!(out, " ")?;
+            visit(&item.children[2], out)?;
+            write!(out, ")")?;
+        }
+        ExprType::ForEachStmt(name) => {
+            write!(out, "(for {} ", name)?;
+            visit(&item.children[0], out)?;
+            write!(out, " ")?;
+            visit(&item.children[1], out)?;
+            write!(out, ")")?;
+        }
+        ExprType::FnDecl(name, params) => {
+            write!(out, "(fn {} ({}) ", name, params.join(" "))?;
+            visit(&item.children[0], out)?;
+            write!(out, ")")?;
+        }
+        ExprType::Lambda(params) => {
+            write!(out, "(lambda ({}) ", params.join(" "))?;
+            visit(&item.children[0], out)?;
+            write!(out, ")")?;
+        }
+        ExprType::Call => {
+            write!(out, "(call")?;
+            for child in &item.children{
+                write!(out, " ")?;
+                visit(child, out)?;
+            }
+            write!(out, ")")?;
+        }
+        ExprType::ReturnStmt => {
+            write!(out, "(return")?;
+            if let Some(value) = item.children.first() {
+                write!(out, " ")?;
+                visit(value, out)?;
             }
+            write!(out, ")")?;
         }
+        ExprType::BreakStmt => {write!(out, "(break)")?}
+        ExprType::ContinueStmt => {write!(out, "(continue)")?}
         ExprType::VarDeclStmt(name) => {
 
-            print!("(= {} ", name);
+            write!(out, "(= {} ", name)?;
 
             match item.children.first(){
-                None => {print!("Nil")}
-                Some(ast) => {visit(ast);}
+                None => {write!(out, "Nil")?}
+                Some(ast) => {visit(ast, out)?;}
             }
-            print!(")");
+            write!(out, ")")?;
         }
     }
+    return Ok(());
 }
\ No newline at end of file
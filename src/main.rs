@@ -1,22 +1,28 @@
-use crate::lexer::{tokenize, Token};
+#![allow(clippy::needless_return, clippy::upper_case_acronyms)]
+
+use parser_demo::{Interpreter, VMOptions, IntegerMode, OverflowPolicy, Limits};
+use parser_demo::diagnostics::render_error;
 use std::env;
 use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
 
-mod lexer;
-mod parser;
-mod lisp_print;
-mod compiler;
-mod vm;
+/// flags of form --name=<number>
+const NUMERIC_FLAGS: [&str; 4] = ["--gc-threshold", "--max-instructions", "--max-stack", "--max-heap"];
 
-use crate::vm::VM;
-use crate::compiler::{Chunk, Compiler};
-use std::io::{BufRead, BufReader};
+/// debug builds trace compilation stages to stdout
+fn trace_channel() -> Option<Box<dyn Write>> {
+    if cfg!(debug_assertions) {
+        return Some(Box::new(io::stdout()));
+    }
+    return None;
+}
 
-fn run_repl(){
+fn run_repl(options:VMOptions, limits:Limits){
     let stdin_buffer = BufReader::new(std::io::stdin());
     let mut stdin_iterator = stdin_buffer.lines();
-    let mut compiler = Compiler::new();
-    let mut vm = VM::new();
+    let mut interpreter = Interpreter::with_options(options);
+    interpreter.set_trace(trace_channel());
+    interpreter.set_limits(limits);
 
     println!("REPL\nto exit type 'exit'");
     loop{
@@ -27,36 +33,8 @@ fn run_repl(){
         }
 
         let s = inp_str.trim();
-        let tokens: Vec<Token> = match tokenize(s)  {
-            Ok(res) => {res}
-            Err(msg) => {println!("{}", msg); continue;}
-        };
-
-        let ast = match parser::parse(&tokens) {
-            Ok(res) => {res}
-            Err(msg) => {println!("{}", msg); continue;}
-        };
-
-        #[cfg(debug_assertions)]
-        lisp_print::visit(&ast); //won't be printed in release
-
-        let code_chunk = compiler.continue_compile(&ast);
-        let code_chunk = match code_chunk {
-            Ok(value) => {value}
-            Err(msg) => {
-                println!("{}", msg);
-                continue;
-            }
-        };
-
-        #[cfg(debug_assertions)]
-        code_chunk.dump_stdout(); //won't be printed in release
-
-        match vm.run(&code_chunk) {
-            Ok(_) => {}
-            Err(msg) => {
-                println!("{}", msg);
-            }
+        if let Err(e) = interpreter.eval_line(s) {
+            print!("{}", render_error(&e, "<repl>", s));
         }
 
     }
@@ -64,71 +42,80 @@ fn run_repl(){
 
 }
 
+/// gc_stats: print statistics of heap after program finishes
+fn run_file(filename:&str, content:&str, options:VMOptions, limits:Limits, gc_stats:bool) {
+    let mut interpreter = Interpreter::with_options(options);
+    interpreter.set_trace(trace_channel());
+    interpreter.set_limits(limits);
+
+    //content is not trimmed so that error positions match the file
+    let result = interpreter.eval(content);
+    if gc_stats {
+        let stats = interpreter.heap_stats();
+        println!("gc: {} allocations, {} collections, {} freed, {} live objects, {} bytes",
+                 stats.allocations, stats.collections, stats.freed, stats.live_objects, stats.bytes);
+    }
+    if let Err(e) = result {
+        print!("{}", render_error(&e, filename, content));
+    }
+}
+
 
 fn main() {
 
     let args:Vec<String> = env::args().collect();
 
     //args[0] - program name
+    let mut options = VMOptions::default();
+    let mut limits = Limits::default();
+    let mut gc_stats = false;
+    let mut positional = Vec::new();
+    for arg in &args[1..] {
+        if let Some((name, value)) = arg.split_once('=').filter(|(name, _)| NUMERIC_FLAGS.contains(name)) {
+            let value:usize = match value.parse() {
+                Ok(value) => {value}
+                Err(_) => {
+                    println!("invalid value of {}: {}", name, value);
+                    return;
+                }
+            };
+            match name {
+                "--gc-threshold" => {options.gc_threshold = value;}
+                "--max-instructions" => {limits.max_instructions = Some(value as u64);}
+                "--max-stack" => {limits.max_stack_length = Some(value);}
+                "--max-heap" => {limits.max_heap_bytes = Some(value);}
+                _ => {unreachable!()}
+            }
+            continue;
+        }
+        match arg.as_str() {
+            "--gc-stats" => {gc_stats = true;}
+            "--bigint" => {options.integer_mode = IntegerMode::Big;}
+            "--overflow=trap" => {options.overflow = OverflowPolicy::Trap;}
+            "--overflow=wrap" => {options.overflow = OverflowPolicy::Wrap;}
+            "--overflow=saturate" => {options.overflow = OverflowPolicy::Saturate;}
+            _ => {positional.push(arg);}
+        }
+    }
 
-    if args.len()==1 {
-        run_repl();
+    if positional.is_empty() {
+        run_repl(options, limits);
         return;
     }
 
-    if args.len()!=2 {
-        println!("usage : exec.exe <filename>");
+    if positional.len()!=1 || positional[0].starts_with("--") {
+        println!("usage : exec.exe [--bigint] [--overflow=trap|wrap|saturate] [--gc-threshold=<bytes>] [--gc-stats]\n\
+                  [--max-instructions=<n>] [--max-stack=<n>] [--max-heap=<bytes>] <filename>");
         return;
     }
 
-    let filename = args.get(1).unwrap();
+    let filename = positional[0];
 
     let content = fs::read_to_string(filename).expect("failed to read file.");
-    #[cfg(debug_assertions)]
-    println!("{}", content);
-
-    let s = content.trim();
-    let tokens: Vec<Token> = match tokenize(s)  {
-        Ok(res) => {res}
-        Err(msg) => {println!("{}", msg); return;}
-    };
-
-    #[cfg(debug_assertions)]
-     println!("{}",
-              tokens
-                  .iter()
-                  .map(|x| format!("{}", x))
-                  .collect::<Vec<String>>()
-                  .join(", ")
-     );
-
-    let ast = match parser::parse(&tokens) {
-        Ok(res) => {res}
-        Err(msg) => {println!("{}", msg); return;}
-    };
-
-    #[cfg(debug_assertions)]
-    lisp_print::visit(&ast); //won't be printed in release
-
-    let code_chunk = Chunk::compile_from(&ast);
-    let code_chunk = match code_chunk {
-        Ok(value) => {value}
-        Err(msg) => {
-            println!("{}", msg);
-            return;
-        }
-    };
-
-    #[cfg(debug_assertions)]
-    code_chunk.dump_stdout(); //won't be printed in release
-
-    let mut vm = VM::new();
-
-    match vm.run(&code_chunk) {
-        Ok(_) => {}
-        Err(msg) => {
-            println!("{}", msg);
-        }
+    if let Some(mut trace) = trace_channel() {
+        let _ = writeln!(trace, "{}", content);
     }
 
+    run_file(filename, &content, options, limits, gc_stats);
+
 }
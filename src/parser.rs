@@ -1,28 +1,68 @@
-use crate::lexer::{Token, MOCK_IDX};
+use crate::lexer::{Token, MOCK_IDX, Span};
 use std::iter::Peekable;
 use std::slice::Iter;
 use crate::lexer::Token::{RBracket, Semicolon};
+use crate::error::{SyntaxError, SyntaxErrorKind};
+use crate::bigint::BigInt;
+use std::rc::Rc;
 
 /*
 GRAMMAR:
 
 program: stmt*;
 
-stmt: print_stmt | assignStmt;
+stmt: print_stmt | var_stmt | fn_decl | if_stmt | while_stmt | for_stmt | jump_stmt | return_stmt | block | expr_stmt;
 
 print_stmt: PRINT expr SEMICOLON;
 
-assignStmt: IDENTIFIER '=' expr SEMICOLON;
 
-expr: addition;
+var_stmt: VAR IDENTIFIER ('=' expr)? SEMICOLON;
 
+fn_decl: FN IDENTIFIER '(' (IDENTIFIER (',' IDENTIFIER)*)? ')' block;
+
+if_stmt: IF '(' expr ')' block (ELSE (block | if_stmt))?;
+
+while_stmt: WHILE '(' expr ')' block;
+
+for_stmt: FOR IDENTIFIER IN expr ('..' expr)? block;
+
+jump_stmt: (BREAK | CONTINUE) SEMICOLON;
+
+return_stmt: RETURN expr? SEMICOLON;
+
+expr_stmt: expr SEMICOLON;
+
+block: '{' stmt* '}';
+
+expr: assignment;
+
+assignment: (IDENTIFIER | call '[' expr ']') ('=' | '+=' | '-=' | '*=' | '/=' | '%=') assignment | or_expr;
+
+or_expr: and_expr ('or' and_expr)*;
+and_expr: negation ('and' negation)*;
+negation: 'not' negation | equality;
+equality: comparison (('=='|'!=') comparison)*;
+comparison: bit_or (('<'|'<='|'>'|'>='|IN) bit_or)*;
+bit_or: bit_xor ('|' bit_xor)*;
+bit_xor: bit_and ('^' bit_and)*;
+bit_and: shift ('&' shift)*;
+shift: addition (('<<'|'>>') addition)*;
 addition: mult (('+'|'-') mult)*;
-mult: term (('*'|'/')term)*;
-term: NUMBER | IDENTIFIER | '(' expr ')';
+mult: unary (('*'|'/'|'div'|'%') unary)*;
+unary: ('-'|'+'|'~') unary | power;
+power: call ('**' unary)?;
+call: term ('(' (expr (',' expr)*)? ')' | '[' expr ']')*;
+term: NUMBER | FLOAT | BOOL | STRING | IDENTIFIER | lambda | list | map | '(' expr ')';
+list: '[' (expr (',' expr)*)? ']';
+map: '{' (expr ':' expr (',' expr ':' expr)*)? '}';
+lambda: FN '(' (IDENTIFIER (',' IDENTIFIER)*)? ')' block;
 
 WS: (' '| '\t'| '\n') -> channel(HIDDEN);
 
 NUMBER: [1-9][0-9]*;
+FLOAT: [0-9]+ ('.' [0-9]+ ([eE] [+-]? [0-9]+)? | [eE] [+-]? [0-9]+);
+BOOL: 'true' | 'false';
+STRING: '"' ('\\' [ntr0\\"] | ~["\\\n])* '"';
 PRINT: 'print';
 SEMICOLON: ';';
 IDENTIFIER: [A-Za-z_][A-Za-z0-9_]*;
@@ -30,13 +70,54 @@ IDENTIFIER: [A-Za-z_][A-Za-z0-9_]*;
  */
 
 pub enum ExprType {
-    Op(char),
+    Op(&'static str),
+    /// short-circuiting 'and' / 'or'
+    Logical(&'static str),
+    Unary(&'static str),
     Literal(i32),
+    /// integer literal that does not fit into i32
+    BigLiteral(Rc<BigInt>),
+    Float(f64),
+    Bool(bool),
+    Str(String),
     Variable(String),
 
     PrintStmt,
-    AssignStmt(String),
+    /// assignment is expression, its value is the assigned one
+    Assign(String),
+    /// variable name and binary operator, a += b is a = a + b
+    CompoundAssign(String, &'static str),
+    /// children: list, index and assigned value
+    IndexAssign,
+    /// binary operator. children: list, index and value, xs[i] += v is xs[i] = xs[i] + v
+    CompoundIndexAssign(&'static str),
+    /// expression evaluated for side effects, value is discarded
+    ExprStmt,
     VarDeclStmt(String),
+    /// children: condition, then-block and optional else-block (or nested if)
+    IfStmt,
+    WhileStmt,
+    /// loop variable name. children: range start, range end (exclusive) and body
+    ForStmt(String),
+    /// loop variable name. children: iterated list, map (its keys) or string and body
+    ForEachStmt(String),
+    BreakStmt,
+    ContinueStmt,
+    /// function name and parameter names. children: body block
+    FnDecl(String, Vec<String>),
+    /// anonymous function literal with parameter names. children: body block
+    Lambda(Vec<String>),
+    /// children: callee and arguments
+    Call,
+    /// children: elements
+    List,
+    /// children: keys and values, interleaved
+    Map,
+    /// children: indexed value and index
+    Index,
+    /// children: optional returned value
+    ReturnStmt,
+    Block,
 
     Program
 }
@@ -44,224 +125,617 @@ pub enum ExprType {
 
 pub struct Expr {
     pub expr_type:ExprType,
-    pub children: Vec<Expr>
+    pub children: Vec<Expr>,
+    /// source region this node was parsed from
+    pub span: Span
 }
 
 impl Expr{
-    pub fn new() -> Expr{
+    pub fn new(expr_type: ExprType, span: Span) -> Expr{
         return Expr{
             children: Vec::new(),
-            expr_type: ExprType::Literal(0)
+            expr_type,
+            span
         }
     }
 }
 
-fn consume<'a>(iterator: &'a mut Peekable<Iter<Token>>, expected:&Token) -> Result<&'a Token, String> {
+fn unexpected_end() -> SyntaxError {
+    SyntaxError::new(SyntaxErrorKind::UnexpectedEnd, Span::at(MOCK_IDX, 0))
+}
+
+fn unexpected_token(token: &Token) -> SyntaxError {
+    SyntaxError::new(SyntaxErrorKind::UnexpectedToken(token.describe()), token.get_span())
+}
+
+fn consume<'a>(iterator: &mut Peekable<Iter<'a, Token>>, expected:&Token) -> Result<&'a Token, SyntaxError> {
     let token = match iterator.next() {
         Some(t) => {t}
-        None => {return Err("unexpected end".to_string())}
+        None => {return Err(unexpected_end())}
     };
 
     if std::mem::discriminant(token)!=std::mem::discriminant(expected){
-        return Err(format!("Expected {}, got {}", expected.get_token_type_name(), token));
+        let kind = SyntaxErrorKind::Expected {
+            expected: expected.get_token_type_name(),
+            found: token.describe()
+        };
+        return Err(SyntaxError::new(kind, token.get_span()));
     }
     return Ok(token);
 }
 
-fn consume_msg<'a>(iterator: &'a mut Peekable<Iter<Token>>, expected:&Token, error_msg:String) -> Result<&'a Token, String> {
+fn consume_msg<'a, F>(iterator: &mut Peekable<Iter<'a, Token>>, expected:&Token, error_kind:F) -> Result<&'a Token, SyntaxError>
+    where F: FnOnce(&Token) -> SyntaxErrorKind {
     let token = match iterator.next() {
         Some(t) => {t}
-        None => {return Err("unexpected end".to_string())}
+        None => {return Err(unexpected_end())}
     };
 
     if std::mem::discriminant(token)!=std::mem::discriminant(expected){
-        return Err(error_msg);
+        return Err(SyntaxError::new(error_kind(token), token.get_span()));
     }
     return Ok(token);
 }
 
-fn term(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, String> {
+fn term(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
     if let Some(token) = iterator.peek(){
         match token{
-            Token::Number(i,_) => {
-                let mut tmp = Expr::new();
-                tmp.expr_type = ExprType::Literal(*i);
+            Token::Number(i, _, _) => {
+                let tmp = Expr::new(ExprType::Literal(*i), token.get_span());
+                iterator.next();
+                return Ok(tmp);
+            }
+
+            Token::BigNumber(n, _, _) => {
+                let tmp = Expr::new(ExprType::BigLiteral(n.clone()), token.get_span());
+                iterator.next();
+                return Ok(tmp);
+            }
+
+            Token::Float(n, _, _) => {
+                let tmp = Expr::new(ExprType::Float(*n), token.get_span());
+                iterator.next();
+                return Ok(tmp);
+            }
+
+            Token::Bool(b, _) => {
+                let tmp = Expr::new(ExprType::Bool(*b), token.get_span());
+                iterator.next();
+                return Ok(tmp);
+            }
+
+            Token::Str(string, _, _) => {
+                let tmp = Expr::new(ExprType::Str(string.clone()), token.get_span());
                 iterator.next();
                 return Ok(tmp);
             }
 
             Token::Identifier(name, _) => {
-                let mut tmp = Expr::new();
-                tmp.expr_type = ExprType::Variable(name.clone());
+                let tmp = Expr::new(ExprType::Variable(name.clone()), token.get_span());
                 iterator.next();
 
                 return Ok(tmp);
             }
 
+            Token::Fn(_) => {
+                let fn_kwrd = iterator.next().unwrap();
+                let (params, body) = function_rest(iterator, fn_kwrd)?;
+                let mut res = Expr::new(ExprType::Lambda(params), fn_kwrd.get_span().join(body.span));
+                res.children.push(body);
+                return Ok(res);
+            }
+
+            Token::LSquare(_) => {
+                let lsquare = iterator.next().unwrap();
+                let mut res = Expr::new(ExprType::List, lsquare.get_span());
+                if !matches!(iterator.peek(), Some(Token::RSquare(_))) {
+                    loop {
+                        res.children.push(expr(iterator)?);
+                        match iterator.peek() {
+                            Some(Token::Comma(_)) => {iterator.next();}
+                            _ => {break;}
+                        }
+                    }
+                }
+                let rsquare = consume(iterator, &Token::RSquare(MOCK_IDX))?;
+                res.span = res.span.join(rsquare.get_span());
+                return Ok(res);
+            }
+
+            Token::LBrace(_) => {
+                let lbrace = iterator.next().unwrap();
+                let mut res = Expr::new(ExprType::Map, lbrace.get_span());
+                if !matches!(iterator.peek(), Some(Token::RBrace(_))) {
+                    loop {
+                        res.children.push(expr(iterator)?);
+                        consume(iterator, &Token::Colon(MOCK_IDX))?;
+                        res.children.push(expr(iterator)?);
+                        match iterator.peek() {
+                            Some(Token::Comma(_)) => {iterator.next();}
+                            _ => {break;}
+                        }
+                    }
+                }
+                let rbrace = consume(iterator, &Token::RBrace(MOCK_IDX))?;
+                res.span = res.span.join(rbrace.get_span());
+                return Ok(res);
+            }
+
             Token::LBracket(r) => {
+                let opening = token.get_span();
                 iterator.next();
-                let expr = expr(iterator)?;
+                let mut expr = expr(iterator)?;
 
-                consume_msg(iterator, &RBracket(MOCK_IDX), format!("expected ')' for opening '(' at {}", r))?;
+                let closing = consume_msg(iterator, &RBracket(MOCK_IDX),
+                            |found| SyntaxErrorKind::UnclosedBracket {opening: *r, found: found.describe()})?;
+                expr.span = opening.join(closing.get_span()); //include brackets
                 return Ok(expr);
             }
             r => {
-                return Err(format!("unexpected token {}", r));}
+                return Err(unexpected_token(r));}
         }
     }else{
-        return Err("unexpected end".to_string());
+        return Err(unexpected_end());
     }
 
 }
 
-fn addition(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, String> {
-    let mut left_node = mult(iterator)?;
+type Rule = fn(&mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError>;
+
+/// parses left-associative chain `operand (OP operand)*` where OP is one of operators
+fn left_assoc(iterator: &mut Peekable<Iter<Token>>, operators: &[&str], operand: Rule,
+              node_type: fn(&'static str) -> ExprType) -> Result<Expr, SyntaxError> {
+    let mut left_node = operand(iterator)?;
     while let Some(token) = iterator.peek() {
-        match token {
-            Token::Op(c, _) if *c=='+'||*c=='-' => {
-                iterator.next();
-                let right_node = mult(iterator)?;
-                let mut tmp = Expr::new();
-                tmp.expr_type = ExprType::Op(*c);
-                tmp.children.push(left_node);
-                tmp.children.push(right_node);
-                left_node = tmp;
-            }
+        //in is keyword of for loop as well as operator
+        let op = match token {
+            Token::Op(op, _) => {*op}
+            Token::In(_) => {"in"}
             _ => {break;}
+        };
+        if !operators.contains(&op) {
+            break;
         }
+        iterator.next();
+        let right_node = operand(iterator)?;
+        let mut tmp = Expr::new(node_type(op), left_node.span.join(right_node.span));
+        tmp.children.push(left_node);
+        tmp.children.push(right_node);
+        left_node = tmp;
     }
     return Ok(left_node);
+}
 
+fn or_expr(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    left_assoc(iterator, &["or"], and_expr, ExprType::Logical)
 }
 
-fn mult(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, String> {
-    let mut left_node = term(iterator)?;
-    while let Some(token) = iterator.peek(){
-        match token {
-            Token::Op(c, _) if *c=='*' || *c=='/' => {
+fn and_expr(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    left_assoc(iterator, &["and"], negation, ExprType::Logical)
+}
+
+fn negation(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    if let Some(Token::Op("not", _)) = iterator.peek() {
+        let not_kwrd = iterator.next().unwrap();
+        let operand = negation(iterator)?;
+        let mut res = Expr::new(ExprType::Unary("not"), not_kwrd.get_span().join(operand.span));
+        res.children.push(operand);
+        return Ok(res);
+    }
+    equality(iterator)
+}
+
+fn equality(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    left_assoc(iterator, &["==", "!="], comparison, ExprType::Op)
+}
+
+fn comparison(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    left_assoc(iterator, &["<", "<=", ">", ">=", "in"], bit_or, ExprType::Op)
+}
+
+fn bit_or(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    left_assoc(iterator, &["|"], bit_xor, ExprType::Op)
+}
+
+fn bit_xor(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    left_assoc(iterator, &["^"], bit_and, ExprType::Op)
+}
+
+fn bit_and(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    left_assoc(iterator, &["&"], shift, ExprType::Op)
+}
+
+fn shift(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    left_assoc(iterator, &["<<", ">>"], addition, ExprType::Op)
+}
+
+fn addition(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    left_assoc(iterator, &["+", "-"], mult, ExprType::Op)
+}
+
+fn mult(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    left_assoc(iterator, &["*", "/", "div", "%"], unary, ExprType::Op)
+}
+
+fn unary(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    if let Some(Token::Op(op @ ("-" | "+" | "~"), _)) = iterator.peek() {
+        let op_token = iterator.next().unwrap();
+        let operand = unary(iterator)?;
+        let mut res = Expr::new(ExprType::Unary(op), op_token.get_span().join(operand.span));
+        res.children.push(operand);
+        return Ok(res);
+    }
+    power(iterator)
+}
+
+/// right-associative, right operand may have sign: 2**-1, -2**2 is -(2**2)
+fn power(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    let base = call(iterator)?;
+    if let Some(Token::Op("**", _)) = iterator.peek() {
+        iterator.next();
+        let exponent = unary(iterator)?;
+        let mut res = Expr::new(ExprType::Op("**"), base.span.join(exponent.span));
+        res.children.push(base);
+        res.children.push(exponent);
+        return Ok(res);
+    }
+    return Ok(base);
+}
+
+/// term followed by any amount of argument lists and indexes, f(1)(2) calls result of f(1), xs[0][1] indexes xs[0]
+fn call(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    let mut callee = term(iterator)?;
+    loop {
+        let lbracket = match iterator.peek() {
+            Some(Token::LBracket(_)) => {iterator.next().unwrap()}
+            Some(Token::LSquare(_)) => {
                 iterator.next();
-                let right_node = term(iterator)?;
-                let mut tmp = Expr::new();
-                tmp.expr_type = ExprType::Op(*c);
-                tmp.children.push(left_node);
-                tmp.children.push(right_node);
-                left_node = tmp;
+                let index = expr(iterator)?;
+                let rsquare = consume(iterator, &Token::RSquare(MOCK_IDX))?;
+                let mut res = Expr::new(ExprType::Index, callee.span.join(rsquare.get_span()));
+                res.children.push(callee);
+                res.children.push(index);
+                callee = res;
+                continue;
             }
             _ => {break;}
+        };
+        let mut res = Expr::new(ExprType::Call, callee.span);
+        res.children.push(callee);
+
+        if !matches!(iterator.peek(), Some(Token::RBracket(_))) {
+            loop {
+                res.children.push(expr(iterator)?);
+                match iterator.peek() {
+                    Some(Token::Comma(_)) => {iterator.next();}
+                    _ => {break;}
+                }
+            }
         }
+
+        let rbracket = consume_msg(iterator, &RBracket(MOCK_IDX),
+                    |found| SyntaxErrorKind::UnclosedBracket {opening: lbracket.get_pos(), found: found.describe()})?;
+        res.span = res.span.join(rbracket.get_span());
+        callee = res;
     }
-    return Ok(left_node);
+    return Ok(callee);
 }
 
-fn expr(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, String> {
-    addition(iterator)
+fn expr(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    assignment(iterator)
 }
 
-fn print_stmt(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, String> {
+/// right-associative, a = b = 3 assigns 3 to both
+fn assignment(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    let target = or_expr(iterator)?;
+
+    let compound_op = match iterator.peek() {
+        Some(Token::Equals(_)) => {None}
+        Some(Token::CompoundAssign(op, _)) => {Some(*op)}
+        _ => {return Ok(target);}
+    };
+    let eq_token = iterator.next().unwrap();
+
+    let value = match assignment(iterator) {
+        Ok(r) => {r}
+        Err(e) => {return Err(e.with_context("expected expression in assignment", eq_token.get_pos()))}
+    };
+    let span = target.span.join(value.span);
+
+    match target.expr_type {
+        ExprType::Variable(name) => {
+            let expr_type = match compound_op {
+                Some(op) => {ExprType::CompoundAssign(name, op)}
+                None => {ExprType::Assign(name)}
+            };
+            let mut res = Expr::new(expr_type, span);
+            res.children.push(value);
+            return Ok(res);
+        }
+        ExprType::Index => {
+            let expr_type = match compound_op {
+                Some(op) => {ExprType::CompoundIndexAssign(op)}
+                None => {ExprType::IndexAssign}
+            };
+            let mut res = Expr::new(expr_type, span);
+            res.children = target.children; //indexed value and index
+            res.children.push(value);
+            return Ok(res);
+        }
+        _ => {return Err(SyntaxError::new(SyntaxErrorKind::InvalidAssignmentTarget, target.span));}
+    }
+}
+
+fn print_stmt(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
     let print_kwrd = iterator.next().unwrap(); //consume print
 
-    let mut res = Expr::new();
-    res.expr_type = ExprType::PrintStmt;
     let sub = expr(iterator);
     let sub = match sub {
         Ok(e) => {e}
-        Err(s) => {return Err(s + "\n" + &*format!("expected expression after print at {}", print_kwrd.get_pos()))}
+        Err(e) => {return Err(e.with_context("expected expression after print", print_kwrd.get_pos()))}
     };
 
-    consume(iterator, &Semicolon(MOCK_IDX))?;
+    let semicolon = consume(iterator, &Semicolon(MOCK_IDX))?;
 
+    let mut res = Expr::new(ExprType::PrintStmt, print_kwrd.get_span().join(semicolon.get_span()));
     res.children.push(sub);
     return Ok(res);
 
 }
 
-fn var_decl_stmt(iterator:&mut Peekable<Iter<Token>>) -> Result<Expr, String> {
-    iterator.next(); //consume var
+fn var_decl_stmt(iterator:&mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    let var_kwrd = iterator.next().unwrap(); //consume var
     let var_name = match consume(iterator, &Token::Identifier("".to_string(), MOCK_IDX)) {
         Ok(t) => {match t{
             Token::Identifier(s, _) => {s.clone()}
-            _ => {return Err("error parsing variable name".to_string())} //shouldn't happen
+            t => {return Err(unexpected_token(t))} //shouldn't happen
         }}
 
-        Err(msg) => {
-            return Err(msg);
+        Err(e) => {
+            return Err(e);
         }
     };
 
-    let mut res = Expr{expr_type:ExprType::VarDeclStmt(var_name), children:Vec::new()};
-    match iterator.peek(){
-        Some(Token::Equals(_)) => {
-            iterator.next(); // consume =
-            let assignee = expr(iterator)?;
-            res.children.push(assignee);
-        }
-        _ => {}
+    let mut children = Vec::new();
+    if let Some(Token::Equals(_)) = iterator.peek(){
+        iterator.next(); // consume =
+        let assignee = expr(iterator)?;
+        children.push(assignee);
     }
-    consume(iterator, &Token::Semicolon(MOCK_IDX))?;
+    let semicolon = consume(iterator, &Token::Semicolon(MOCK_IDX))?;
+
+    let mut res = Expr::new(ExprType::VarDeclStmt(var_name), var_kwrd.get_span().join(semicolon.get_span()));
+    res.children = children;
     return Ok(res);
 }
 
 
-fn assign_stmt(iterator:&mut Peekable<Iter<Token>>) -> Result<Expr, String> {
-    //let var_name = iterator.next(); //take and cosume name
-    let var_name = match consume(iterator, &Token::Identifier("".to_string(), MOCK_IDX))  {
-        Ok(r) => {
-            match r {
-                Token::Identifier(a, _) => {a.clone()}
-                _ => {return Err("error getting variable name".to_string())}
+fn expr_stmt(iterator:&mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    let sub = expr(iterator)?;
+    let semicolon = consume(iterator, &Token::Semicolon(MOCK_IDX))?;
+
+    let mut res = Expr::new(ExprType::ExprStmt, sub.span.join(semicolon.get_span()));
+    res.children.push(sub);
+    return Ok(res);
+}
+
+/// parses parameter list and body of function, shared by declarations and lambdas
+fn function_rest(iterator:&mut Peekable<Iter<Token>>, fn_kwrd:&Token) -> Result<(Vec<String>, Expr), SyntaxError> {
+    let lbracket = consume(iterator, &Token::LBracket(MOCK_IDX))
+        .map_err(|e| e.with_context("expected parameter list of function", fn_kwrd.get_pos()))?;
+    let mut params = Vec::new();
+    if let Some(Token::Identifier(..)) = iterator.peek() {
+        loop {
+            match consume(iterator, &Token::Identifier("".to_string(), MOCK_IDX))? {
+                Token::Identifier(param, _) => {params.push(param.clone());}
+                t => {return Err(unexpected_token(t))}
+            }
+            match iterator.peek() {
+                Some(Token::Comma(_)) => {iterator.next();}
+                _ => {break;}
             }
         }
-        Err(msg) => {return Err(msg)}
-    };
+    }
+    consume_msg(iterator, &RBracket(MOCK_IDX),
+                |found| SyntaxErrorKind::UnclosedBracket {opening: lbracket.get_pos(), found: found.describe()})?;
 
-    let eq_idx = consume(iterator, &Token::Equals(MOCK_IDX))?.get_pos();
+    let body = block(iterator)?;
+    return Ok((params, body));
+}
 
-    let sub = match expr(iterator) {
-        Ok(r) => {r}
-        Err(msg) => {return Err(msg + "\n" + &*format!("expected expression in assignment at {}\n", eq_idx))}
+fn fn_decl(iterator:&mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    let fn_kwrd = iterator.next().unwrap(); //consume fn
+
+    let name = match consume(iterator, &Token::Identifier("".to_string(), MOCK_IDX))? {
+        Token::Identifier(name, _) => {name.clone()}
+        t => {return Err(unexpected_token(t))}
     };
 
-    consume(iterator, &Token::Semicolon(MOCK_IDX))?;
+    let (params, body) = function_rest(iterator, fn_kwrd)?;
 
-    let mut res = Expr::new();
-    res.expr_type = ExprType::AssignStmt(var_name);
-    res.children.push(sub);
+    let mut res = Expr::new(ExprType::FnDecl(name, params), fn_kwrd.get_span().join(body.span));
+    res.children.push(body);
     return Ok(res);
 }
 
-fn stmt(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, String> {
+fn return_stmt(iterator:&mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    let return_kwrd = iterator.next().unwrap(); //consume return
+
+    let mut children = Vec::new();
+    match iterator.peek() {
+        Some(Token::Semicolon(_)) => {}
+        _ => {
+            let value = expr(iterator)
+                .map_err(|e| e.with_context("expected value or ';' after return", return_kwrd.get_pos()))?;
+            children.push(value);
+        }
+    }
+    let semicolon = consume(iterator, &Token::Semicolon(MOCK_IDX))?;
+
+    let mut res = Expr::new(ExprType::ReturnStmt, return_kwrd.get_span().join(semicolon.get_span()));
+    res.children = children;
+    return Ok(res);
+}
+
+fn block(iterator:&mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    let lbrace = consume(iterator, &Token::LBrace(MOCK_IDX))?;
+    let mut children = Vec::new();
+    while let Some(token) = iterator.peek() {
+        match token {
+            Token::RBrace(_) | Token::EOF(_) => {break;}
+            _ => {children.push(stmt(iterator)?);}
+        }
+    }
+    let rbrace = consume(iterator, &Token::RBrace(MOCK_IDX))
+        .map_err(|e| e.with_context("expected '}' for block opened", lbrace.get_pos()))?;
+
+    let mut res = Expr::new(ExprType::Block, lbrace.get_span().join(rbrace.get_span()));
+    res.children = children;
+    return Ok(res);
+}
+
+/// parses '(' expr ')' after if or while
+fn condition(iterator:&mut Peekable<Iter<Token>>, keyword:&Token) -> Result<Expr, SyntaxError> {
+    let lbracket = consume(iterator, &Token::LBracket(MOCK_IDX))
+        .map_err(|e| e.with_context(&format!("expected condition in brackets after {}", keyword.get_token_type_name()),
+                                    keyword.get_pos()))?;
+    let condition = expr(iterator)?;
+    consume_msg(iterator, &RBracket(MOCK_IDX),
+                |found| SyntaxErrorKind::UnclosedBracket {opening: lbracket.get_pos(), found: found.describe()})?;
+    return Ok(condition);
+}
+
+fn if_stmt(iterator:&mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    let if_kwrd = iterator.next().unwrap(); //consume if
+
+    let condition = condition(iterator, if_kwrd)?;
+
+    let then_branch = block(iterator)?;
+    let mut end = then_branch.span;
+    let mut children = vec![condition, then_branch];
+
+    if let Some(Token::Else(_)) = iterator.peek() {
+        iterator.next(); //consume else
+        let else_branch = match iterator.peek() {
+            Some(Token::If(_)) => {if_stmt(iterator)?} // else if
+            _ => {block(iterator)?}
+        };
+        end = else_branch.span;
+        children.push(else_branch);
+    }
+
+    let mut res = Expr::new(ExprType::IfStmt, if_kwrd.get_span().join(end));
+    res.children = children;
+    return Ok(res);
+}
+
+fn while_stmt(iterator:&mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    let while_kwrd = iterator.next().unwrap(); //consume while
+
+    let condition = condition(iterator, while_kwrd)?;
+    let body = block(iterator)?;
+
+    let mut res = Expr::new(ExprType::WhileStmt, while_kwrd.get_span().join(body.span));
+    res.children = vec![condition, body];
+    return Ok(res);
+}
+
+fn for_stmt(iterator:&mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    let for_kwrd = iterator.next().unwrap(); //consume for
+
+    let var_name = match consume(iterator, &Token::Identifier("".to_string(), MOCK_IDX))? {
+        Token::Identifier(name, _) => {name.clone()}
+        t => {return Err(unexpected_token(t))}
+    };
+    consume(iterator, &Token::In(MOCK_IDX))?;
+    let start = expr(iterator)?;
+    if !matches!(iterator.peek(), Some(Token::DotDot(_))) {
+        //not a range, iteration over collection
+        let body = block(iterator)?;
+        let mut res = Expr::new(ExprType::ForEachStmt(var_name), for_kwrd.get_span().join(body.span));
+        res.children = vec![start, body];
+        return Ok(res);
+    }
+    iterator.next();
+    let end = expr(iterator)?;
+    let body = block(iterator)?;
+
+    let mut res = Expr::new(ExprType::ForStmt(var_name), for_kwrd.get_span().join(body.span));
+    res.children = vec![start, end, body];
+    return Ok(res);
+}
+
+/// break or continue
+fn jump_stmt(iterator:&mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
+    let keyword = iterator.next().unwrap();
+    let semicolon = consume(iterator, &Token::Semicolon(MOCK_IDX))?;
+    let expr_type = match keyword {
+        Token::Break(_) => {ExprType::BreakStmt}
+        _ => {ExprType::ContinueStmt}
+    };
+    return Ok(Expr::new(expr_type, keyword.get_span().join(semicolon.get_span())));
+}
+
+fn stmt(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, SyntaxError> {
     if let Some(token) = iterator.peek() {
         match *token {
             Token::Print(_) => { return print_stmt(iterator); }
             Token::Var(_) => {return var_decl_stmt(iterator); }
-            Token::Identifier(..) => { return assign_stmt(iterator); }
-            r => {return Err(format!("unexpected token {}", r))}
+            Token::Fn(_) => {return fn_decl(iterator); }
+            Token::Return(_) => {return return_stmt(iterator); }
+            Token::If(_) => {return if_stmt(iterator); }
+            Token::While(_) => {return while_stmt(iterator); }
+            Token::For(_) => {return for_stmt(iterator); }
+            Token::Break(_) | Token::Continue(_) => {return jump_stmt(iterator); }
+            Token::LBrace(_) => {return block(iterator); }
+            _ => {return expr_stmt(iterator); }
         }
     }else{
-        return Err("unexpected end of string".to_string());
+        return Err(unexpected_end());
+    }
+}
+
+/// amount of braces left open by tokens
+fn brace_depth<'a>(tokens: impl Iterator<Item=&'a Token>) -> usize {
+    let mut depth = 0usize;
+    for token in tokens {
+        match token {
+            Token::LBrace(_) => {depth += 1;}
+            Token::RBrace(_) => {depth = depth.saturating_sub(1);}
+            _ => {}
+        }
     }
+    return depth;
 }
 
-fn program(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, String> {
-    let mut res = Expr::new();
-    res.expr_type = ExprType::Program;
-    let mut had_error = false;
-    let mut err_msg = String::new();
+fn program(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, Vec<SyntaxError>> {
+    let start = iterator.peek().map(|t| t.get_span()).unwrap_or_else(|| Span::at(MOCK_IDX, 0));
+    let mut res = Expr::new(ExprType::Program, start);
+    let mut errors = Vec::new();
     while let Some(x) = iterator.peek() {
         match x {
-            Token::EOF(..) => {break;}
+            Token::EOF(..) => {
+                res.span = start.join(x.get_span());
+                break;
+            }
             _ => {
+                let stmt_start = iterator.clone();
                 let expr_ = match stmt(iterator){
                     Ok(t) => {t}
-                    Err(msg) => { //error parsing
-                        had_error = true;
-                        err_msg.push('\n');
-                        err_msg.push_str(&*msg);
-                        //synchronise, report error later
-                        while let Some(x) = iterator.next() {
-                            match x{ //read till semicolon, continue as if nothing happened
-                                Token::Semicolon(_) => {break;}
-                                _ =>{}
+                    Err(e) => { //error parsing
+                        errors.push(e);
+                        //synchronise, report error later.
+                        //blocks opened by failed statement are skipped as a whole, otherwise parsing resumes inside of them
+                        let consumed = stmt_start.len()-iterator.len();
+                        let mut depth = brace_depth(stmt_start.take(consumed));
+                        for x in iterator.by_ref() {
+                            //read till end of statement or block, continue as if nothing happened
+                            match x {
+                                Token::LBrace(_) => {depth += 1;}
+                                Token::RBrace(_) if depth<=1 => {break;}
+                                Token::RBrace(_) => {depth -= 1;}
+                                Token::Semicolon(_) if depth==0 => {break;}
+                                _ => {}
                             }
                         }
                         continue;
@@ -271,14 +745,34 @@ fn program(iterator: &mut Peekable<Iter<Token>>) -> Result<Expr, String> {
         }
 
     }
-    if had_error {
-        return Err(err_msg);
+    if !errors.is_empty() {
+        return Err(errors);
     }
 
     return Ok(res);
 }
 
-pub fn parse(tokens:&Vec<Token>) -> Result<Expr, String> {
+pub fn parse(tokens:&[Token]) -> Result<Expr, Vec<SyntaxError>> {
     let mut iterator: Peekable<Iter<Token>> = tokens.iter().peekable();
     program(&mut iterator)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+    use crate::vm::IntegerMode;
+
+    fn error_count(source:&str) -> usize {
+        let tokens = tokenize(source, IntegerMode::Fixed).unwrap();
+        return parse(&tokens).err().map(|errors| errors.len()).unwrap_or(0);
+    }
+
+    #[test]
+    fn recovery_skips_rest_of_block() {
+        assert_eq!(error_count("fn f() {\n print (1;\n var x = 2;\n}"), 1);
+        assert_eq!(error_count("while (true) {\n if (1 {\n print 1;\n }\n print 2;\n}\nprint 3;"), 1);
+        assert_eq!(error_count("fn f() { print (1; }\nvar x = ;\nfn g() { print 2; }"), 2);
+        assert_eq!(error_count("var x = 1 2;\nprint x;"), 1);
+        assert_eq!(error_count("fn f() {\n print 1;\n}"), 0);
+    }
+}
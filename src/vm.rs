@@ -0,0 +1,1357 @@
+#[derive(Copy, Clone, Debug)]
+pub enum OpCode{
+    Add, Sub, Mult, Div,
+    /// div and % round toward negative infinity
+    FloorDiv, Mod, Pow,
+    BitAnd, BitOr, BitXor, Shl, Shr,
+    /// unary -, + and ~
+    Negate, Positive, BitNot,
+    Equal, NotEqual, Less, LessEqual, Greater, GreaterEqual,
+    Not,
+    True, False,
+    Store(u8), LoadVar(u8), LoadConst(u8),
+    /// locals live on stack above variable area
+    StoreLocal(u8), LoadLocal(u8),
+    Pop,
+    /// pushes copy of top of stack
+    Dup,
+    /// jumps use absolute instruction index as target, conditional jumps do not pop condition
+    Jump(u8), JumpIfFalse(u8), JumpIfTrue(u8),
+    /// creates closure of function from chunk's function table
+    Closure(u8),
+    /// variables of enclosing functions, captured by closure
+    StoreUpvalue(u8), LoadUpvalue(u8),
+    /// moves captured local at top of stack into its upvalue and pops it
+    CloseUpvalue,
+    /// operand is amount of arguments, callee is below them on stack
+    Call(u8), Return,
+    /// operand is index of native function, its arguments are on stack
+    CallNative(u8),
+    /// operand is amount of elements taken from stack
+    BuildList(u8),
+    /// operand is amount of key-value pairs taken from stack
+    BuildMap(u8),
+    /// SetIndex leaves assigned value on stack
+    Index, SetIndex, Len,
+    /// x in collection: map key, list element or substring
+    Contains,
+    /// element of list, key of map or character of string at position, used by for loops
+    IterItem,
+    /// pushes copies of two values at top of stack
+    DupTwo,
+    Extend(u8),
+    Print
+}
+
+impl Display for OpCode{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            OpCode::Add => {"[ADD]".to_string()}
+            OpCode::Sub => {"[SUB]".to_string()}
+            OpCode::Mult => {"[MULT]".to_string()}
+            OpCode::Div => {"[DIV]".to_string()}
+            OpCode::FloorDiv => {"[FLOOR_DIV]".to_string()}
+            OpCode::Mod => {"[MOD]".to_string()}
+            OpCode::Pow => {"[POW]".to_string()}
+            OpCode::BitAnd => {"[BIT_AND]".to_string()}
+            OpCode::BitOr => {"[BIT_OR]".to_string()}
+            OpCode::BitXor => {"[BIT_XOR]".to_string()}
+            OpCode::Shl => {"[SHL]".to_string()}
+            OpCode::Shr => {"[SHR]".to_string()}
+            OpCode::Negate => {"[NEGATE]".to_string()}
+            OpCode::Positive => {"[POSITIVE]".to_string()}
+            OpCode::BitNot => {"[BIT_NOT]".to_string()}
+            OpCode::Equal => {"[EQUAL]".to_string()}
+            OpCode::NotEqual => {"[NOT_EQUAL]".to_string()}
+            OpCode::Less => {"[LESS]".to_string()}
+            OpCode::LessEqual => {"[LESS_EQUAL]".to_string()}
+            OpCode::Greater => {"[GREATER]".to_string()}
+            OpCode::GreaterEqual => {"[GREATER_EQUAL]".to_string()}
+            OpCode::Not => {"[NOT]".to_string()}
+            OpCode::True => {"[TRUE]".to_string()}
+            OpCode::False => {"[FALSE]".to_string()}
+            OpCode::Store(idx) => {format!("[STORE {}]", idx)}
+            OpCode::LoadVar(idx) => {format!("[LOAD_VAR {}]", idx)}
+            OpCode::StoreLocal(idx) => {format!("[STORE_LOCAL {}]", idx)}
+            OpCode::LoadLocal(idx) => {format!("[LOAD_LOCAL {}]", idx)}
+            OpCode::Pop => {"[POP]".to_string()}
+            OpCode::Dup => {"[DUP]".to_string()}
+            OpCode::Jump(idx) => {format!("[JUMP {}]", idx)}
+            OpCode::JumpIfFalse(idx) => {format!("[JUMP_IF_FALSE {}]", idx)}
+            OpCode::JumpIfTrue(idx) => {format!("[JUMP_IF_TRUE {}]", idx)}
+            OpCode::Print => {"[PRINT]".to_string()}
+            OpCode::Extend(idx) => {format!("[EXTEND {}]", idx)}
+            OpCode::LoadConst(idx) => {format!("[LOAD_CONST {}]", idx)}
+            OpCode::Closure(idx) => {format!("[CLOSURE {}]", idx)}
+            OpCode::StoreUpvalue(idx) => {format!("[STORE_UPVALUE {}]", idx)}
+            OpCode::LoadUpvalue(idx) => {format!("[LOAD_UPVALUE {}]", idx)}
+            OpCode::CloseUpvalue => {"[CLOSE_UPVALUE]".to_string()}
+            OpCode::Call(argc) => {format!("[CALL {}]", argc)}
+            OpCode::Return => {"[RETURN]".to_string()}
+            OpCode::CallNative(idx) => {format!("[CALL_NATIVE {}]", idx)}
+            OpCode::BuildList(count) => {format!("[BUILD_LIST {}]", count)}
+            OpCode::BuildMap(count) => {format!("[BUILD_MAP {}]", count)}
+            OpCode::Contains => {"[CONTAINS]".to_string()}
+            OpCode::IterItem => {"[ITER_ITEM]".to_string()}
+            OpCode::Index => {"[INDEX]".to_string()}
+            OpCode::SetIndex => {"[SET_INDEX]".to_string()}
+            OpCode::Len => {"[LEN]".to_string()}
+            OpCode::DupTwo => {"[DUP_TWO]".to_string()}
+        })
+    }
+}
+
+/// captured variable. stays on stack while its frame is alive, then is moved into upvalue
+pub enum Upvalue {
+    /// absolute stack index
+    Open(usize),
+    Closed(Value)
+}
+
+/// function together with variables it captured
+pub struct Closure {
+    pub function:Rc<Function>,
+    pub upvalues:Vec<Rc<RefCell<Upvalue>>>
+}
+
+/// key of map, only integers and strings can be hashed
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Int(i32),
+    BigInt(Rc<BigInt>),
+    Str(Rc<str>)
+}
+
+impl MapKey {
+    fn from_value(value:&Value) -> Result<MapKey, RuntimeErrorKind> {
+        match value {
+            Value::Int(i) => {Ok(MapKey::Int(*i))}
+            Value::BigInt(n) => {Ok(MapKey::BigInt(n.clone()))}
+            Value::Str(s) => {Ok(MapKey::Str(s.clone()))}
+            other => {Err(RuntimeErrorKind::UnhashableKey(other.type_name()))}
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            MapKey::Int(i) => {Value::Int(*i)}
+            MapKey::BigInt(n) => {Value::BigInt(n.clone())}
+            MapKey::Str(s) => {Value::Str(s.clone())}
+        }
+    }
+}
+
+/// hash map that keeps keys in insertion order, iteration follows it
+#[derive(Default)]
+pub struct Map {
+    entries:Vec<(MapKey, Value)>,
+    /// position of key in entries
+    positions:HashMap<MapKey, usize>
+}
+
+impl Map {
+    pub fn get(&self, key:&MapKey) -> Option<&Value> {
+        self.positions.get(key).map(|position| &self.entries[*position].1)
+    }
+
+    pub fn contains_key(&self, key:&MapKey) -> bool {
+        self.positions.contains_key(key)
+    }
+
+    /// existing key keeps its position. returns whether key is new
+    pub fn insert(&mut self, key:MapKey, value:Value) -> bool {
+        match self.positions.get(&key) {
+            Some(position) => {
+                self.entries[*position].1 = value;
+                return false;
+            }
+            None => {
+                self.positions.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+                return true;
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn key_at(&self, position:usize) -> &MapKey {
+        &self.entries[position].0
+    }
+
+    /// in insertion order
+    pub fn entries(&self) -> impl Iterator<Item=(&MapKey, &Value)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item=&Value> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+}
+
+#[derive(Clone)]
+pub enum Value {
+    Int(i32),
+    /// only in big integer mode, for integers that do not fit into i32
+    BigInt(Rc<BigInt>),
+    Float(f64),
+    Bool(bool),
+    Str(Rc<str>),
+    //objects in heap. they are shared, assignment to element of list is visible through every reference
+    Closure(ObjRef),
+    List(ObjRef),
+    Map(ObjRef)
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) | Value::BigInt(_) => {"int"}
+            Value::Float(_) => {"float"}
+            Value::Bool(_) => {"bool"}
+            Value::Str(_) => {"string"}
+            Value::Closure(_) => {"function"}
+            Value::List(_) => {"list"}
+            Value::Map(_) => {"map"}
+        }
+    }
+
+    /// big integer is kept only if it does not fit into i32
+    fn from_big(n:BigInt) -> Value {
+        match n.to_i32() {
+            Some(i) => {Value::Int(i)}
+            None => {Value::BigInt(Rc::new(n))}
+        }
+    }
+
+    fn as_big(&self) -> Option<BigInt> {
+        match self {
+            Value::Int(i) => {Some(BigInt::from(*i))}
+            Value::BigInt(n) => {Some(n.as_ref().clone())}
+            _ => {None}
+        }
+    }
+
+    /// values passed between scripts and host program. objects in heap are owned by VM,
+    /// their handles become invalid once VM frees them, so they do not leave it
+    pub fn check_host_value(&self) -> Result<(), RuntimeErrorKind> {
+        match self.obj_ref() {
+            Some(_) => {Err(RuntimeErrorKind::TypeMismatch {expected: "int, float, bool or string", found: self.type_name()})}
+            None => {Ok(())}
+        }
+    }
+
+    /// heap object value refers to
+    pub fn obj_ref(&self) -> Option<ObjRef> {
+        match self {
+            Value::Closure(obj) | Value::List(obj) | Value::Map(obj) => {Some(*obj)}
+            _ => {None}
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(i) => {Some(*i as f64)}
+            Value::BigInt(n) => {Some(n.to_f64())}
+            Value::Float(n) => {Some(*n)}
+            _ => {None}
+        }
+    }
+}
+
+//conversions used by host program to pass values in and out of scripts
+
+impl From<i32> for Value {
+    fn from(i: i32) -> Self {
+        Value::Int(i)
+    }
+}
+
+impl From<BigInt> for Value {
+    fn from(n: BigInt) -> Self {
+        Value::from_big(n)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Float(n)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Str(s.into())
+    }
+}
+
+impl TryFrom<Value> for i32 {
+    type Error = RuntimeErrorKind;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Int(i) => {Ok(i)}
+            Value::BigInt(_) => {Err(RuntimeErrorKind::IntegerOverflow)}
+            other => {Err(RuntimeErrorKind::TypeMismatch {expected: "int", found: other.type_name()})}
+        }
+    }
+}
+
+impl TryFrom<Value> for BigInt {
+    type Error = RuntimeErrorKind;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        value.as_big().ok_or_else(|| RuntimeErrorKind::TypeMismatch {expected: "int", found: value.type_name()})
+    }
+}
+
+/// integers are converted too
+impl TryFrom<Value> for f64 {
+    type Error = RuntimeErrorKind;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        value.as_f64().ok_or_else(|| RuntimeErrorKind::TypeMismatch {expected: "float", found: value.type_name()})
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = RuntimeErrorKind;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Bool(b) => {Ok(b)}
+            other => {Err(RuntimeErrorKind::TypeMismatch {expected: "bool", found: other.type_name()})}
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = RuntimeErrorKind;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Str(s) => {Ok(s.to_string())}
+            other => {Err(RuntimeErrorKind::TypeMismatch {expected: "string", found: other.type_name()})}
+        }
+    }
+}
+
+/// compares scalars, objects in heap are equal only to themselves. see Heap::values_equal for structural equality
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => {a==b}
+            (Value::BigInt(a), Value::BigInt(b)) => {a==b} //big integers never fit into i32, so never equal to Int
+            (Value::Float(a), Value::Float(b)) => {a==b}
+            (Value::Int(_) | Value::BigInt(_), Value::Float(b)) => {self.as_f64()==Some(*b)}
+            (Value::Float(a), Value::Int(_) | Value::BigInt(_)) => {other.as_f64()==Some(*a)}
+            (Value::Bool(a), Value::Bool(b)) => {a==b}
+            (Value::Str(a), Value::Str(b)) => {a==b}
+            (Value::Closure(a), Value::Closure(b)) => {a==b}
+            (Value::List(a), Value::List(b)) => {a==b}
+            (Value::Map(a), Value::Map(b)) => {a==b}
+            _ => {false}
+        }
+    }
+}
+
+use crate::compiler::{Chunk, Constant, Function};
+use crate::heap::{Heap, HeapStats, Object, ObjRef, MAP_ENTRY_SIZE, DEFAULT_GC_THRESHOLD};
+use crate::bigint::BigInt;
+use crate::error::{RuntimeError, RuntimeErrorKind, Limit};
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// how integers are represented
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum IntegerMode {
+    /// 32-bit integers, overflow is handled according to OverflowPolicy
+    #[default]
+    Fixed,
+    /// integers grow as needed, arithmetic is exact
+    Big
+}
+
+/// what fixed size integer arithmetic does when result does not fit into i32
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum OverflowPolicy {
+    /// stop with runtime error
+    #[default]
+    Trap,
+    /// two's complement wrap around
+    Wrap,
+    /// clamp to i32::MIN or i32::MAX
+    Saturate
+}
+
+/// settings chosen when VM is constructed
+#[derive(Copy, Clone, Debug)]
+pub struct VMOptions {
+    pub integer_mode:IntegerMode,
+    /// ignored in big integer mode
+    pub overflow:OverflowPolicy,
+    /// approximate size of heap in bytes below which garbage is not collected
+    pub gc_threshold:usize
+}
+
+/// limits of single run, None means unlimited
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Limits {
+    pub max_instructions:Option<u64>,
+    /// including variable area
+    pub max_stack_length:Option<usize>,
+    /// approximate size of objects in heap after garbage collection
+    pub max_heap_bytes:Option<usize>
+}
+
+impl Default for VMOptions {
+    fn default() -> Self {
+        VMOptions{
+            integer_mode: IntegerMode::default(),
+            overflow: OverflowPolicy::default(),
+            gc_threshold: DEFAULT_GC_THRESHOLD
+        }
+    }
+}
+
+/// operands of binary numeric operator. int is promoted to big integer when other operand is big
+/// and to float when other operand is float
+enum Numbers {
+    Ints(i32, i32),
+    Bigs(BigInt, BigInt),
+    Floats(f64, f64)
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Arithmetic {
+    Add, Sub, Mult, Div, FloorDiv, Mod, Pow
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Bitwise {
+    And, Or, Xor, Shl, Shr
+}
+
+/// quotient rounded toward negative infinity, None on overflow. b is not zero
+fn floor_div(a:i32, b:i32) -> Option<i32> {
+    let quotient = a.checked_div(b)?;
+    if a%b!=0 && (a<0)!=(b<0) {
+        return Some(quotient-1);
+    }
+    return Some(quotient);
+}
+
+/// remainder with sign of b, never overflows. b is not zero
+fn floor_mod(a:i32, b:i32) -> i32 {
+    let rem = a.wrapping_rem(b); //i32::MIN % -1 is 0
+    if rem!=0 && (rem<0)!=(b<0) {
+        return rem+b;
+    }
+    return rem;
+}
+
+/// max amount of nested calls
+const MAX_FRAMES: usize = 1024;
+
+/// function of host program callable from scripts. receives arguments, error message stops the script
+pub type NativeFunction = dyn Fn(&[Value]) -> Result<Value, String>;
+
+struct Native {
+    name:String,
+    arity:usize,
+    function:Box<NativeFunction>
+}
+
+/// function being executed
+struct CallFrame {
+    closure:ObjRef,
+    function:Rc<Function>,
+    /// instruction of caller to continue from after return
+    return_ip:usize,
+    /// stack index of first argument, locals are counted from it
+    base:usize
+}
+
+pub struct VM{
+    pub stack:Vec<Value>,
+    pub initial_stack_size:usize,
+    frames:Vec<CallFrame>,
+    /// base of current call frame, equals initial_stack_size for top level code
+    frame_base:usize,
+    /// upvalues pointing to stack, shared by all closures that capture same variable
+    open_upvalues:Vec<Rc<RefCell<Upvalue>>>,
+    /// lists, maps and closures
+    heap:Heap,
+    /// indexed by operand of CallNative
+    natives:Vec<Native>,
+    /// receives output of print statements
+    out:Box<dyn Write>,
+    /// set by other threads to stop running code
+    interrupt:Arc<AtomicBool>,
+    /// limits of current run
+    limits:Limits,
+    options:VMOptions
+}
+
+impl VM{
+    pub fn new() -> VM {
+        return VM::with_options(VMOptions::default());
+    }
+
+    pub fn with_options(options:VMOptions) -> VM {
+        return VM{stack:Vec::new(),
+            initial_stack_size: 0,
+            frames: Vec::new(),
+            frame_base: 0,
+            open_upvalues: Vec::new(),
+            heap: Heap::new(options.gc_threshold),
+            natives: Vec::new(),
+            out: Box::new(io::stdout()),
+            interrupt: Arc::new(AtomicBool::new(false)),
+            limits: Limits::default(),
+            options
+        }
+    }
+
+    /// setting the flag stops running code with Interrupted error. flag is cleared when it happens
+    /// and when run starts, so it does not affect code run after the one it was meant for
+    pub fn interrupt_handle(&self) -> Arc<AtomicBool> {
+        return self.interrupt.clone();
+    }
+
+    /// print statements write to out instead of stdout
+    pub fn set_output(&mut self, out:Box<dyn Write>) {
+        self.out = out;
+    }
+
+    /// value of top-level variable by its slot (see Compiler::global_index).
+    /// lists, maps and functions can not be taken out of VM
+    pub fn global(&self, idx:usize) -> Result<Value, RuntimeErrorKind> {
+        if idx>=self.initial_stack_size {
+            return Err(RuntimeErrorKind::BadVariableIndex);
+        }
+        let value = self.stack[idx].clone();
+        value.check_host_value()?;
+        return Ok(value);
+    }
+
+    /// assigns top-level variable, slots up to idx are allocated if needed.
+    /// must not be called while code is running
+    pub fn set_global(&mut self, idx:usize, value:Value) -> Result<(), RuntimeErrorKind> {
+        value.check_host_value()?;
+        if idx>=self.initial_stack_size {
+            self.stack.resize(idx+1, Value::Int(0));
+            self.initial_stack_size = idx+1;
+            self.frame_base = self.initial_stack_size;
+        }
+        self.stack[idx] = value;
+        return Ok(());
+    }
+
+    /// returns index of native, compiler needs it to emit calls (see Compiler::declare_native)
+    pub fn register_native<F>(&mut self, name:&str, arity:usize, function:F) -> usize
+        where F: Fn(&[Value]) -> Result<Value, String> + 'static {
+        self.natives.push(Native{name: name.to_string(), arity, function: Box::new(function)});
+        return self.natives.len()-1;
+    }
+
+    pub fn heap_stats(&self) -> HeapStats {
+        self.heap.stats()
+    }
+
+    fn collect_garbage(&mut self) {
+        let frame_closures = self.frames.iter().map(|frame| frame.closure);
+        self.heap.collect(self.stack.iter(), frame_closures);
+    }
+
+    /// called by instructions that allocate before they take their operands from stack,
+    /// so that everything they use is still rooted
+    fn collect_if_needed(&mut self) {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+    }
+
+    /// checked before every instruction
+    fn check_budget(&mut self, executed:&mut u64) -> Result<(), RuntimeErrorKind> {
+        if self.interrupt.swap(false, Ordering::Relaxed) {
+            return Err(RuntimeErrorKind::Interrupted);
+        }
+        if self.limits.max_instructions.is_some_and(|max| *executed>=max) {
+            return Err(RuntimeErrorKind::LimitExceeded(Limit::Instructions));
+        }
+        *executed += 1;
+        return Ok(());
+    }
+
+    /// checked after every instruction, when all values in use are on stack
+    fn check_memory(&mut self) -> Result<(), RuntimeErrorKind> {
+        if self.limits.max_stack_length.is_some_and(|max| self.stack.len()>max) {
+            return Err(RuntimeErrorKind::LimitExceeded(Limit::StackLength));
+        }
+        if let Some(max) = self.limits.max_heap_bytes {
+            if self.heap.bytes()>max {
+                //part of heap may be garbage
+                self.collect_garbage();
+                if self.heap.bytes()>max {
+                    return Err(RuntimeErrorKind::LimitExceeded(Limit::HeapBytes));
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// fails before instruction makes single value larger than heap limit
+    fn check_allocation(&self, bytes:u64) -> Result<(), RuntimeErrorKind> {
+        if self.limits.max_heap_bytes.is_some_and(|max| bytes>max as u64) {
+            return Err(RuntimeErrorKind::LimitExceeded(Limit::HeapBytes));
+        }
+        return Ok(());
+    }
+
+    /// pushes result of instruction, strings and big integers it creates are counted as part of heap
+    fn push_new(&mut self, value:Value) {
+        self.heap.account_value(&value);
+        self.stack.push(value);
+    }
+
+    fn checked_stack_pop(&mut self) -> Result<Value, RuntimeErrorKind>{
+        if self.stack.len()==self.frame_base {
+            return Err(RuntimeErrorKind::StackUnderflow); //underflow into variables of caller
+        }
+        return self.stack.pop().ok_or(RuntimeErrorKind::StackUnderflow);
+    }
+
+    fn checked_stack_peek(&self) -> Result<Value, RuntimeErrorKind>{
+        if self.stack.len()==self.frame_base {
+            return Err(RuntimeErrorKind::StackUnderflow);
+        }
+        return self.stack.last().cloned().ok_or(RuntimeErrorKind::StackUnderflow);
+    }
+
+    fn numbers(operator:&'static str, a:Value, b:Value) -> Result<Numbers, RuntimeErrorKind> {
+        if let (Value::Int(a), Value::Int(b)) = (&a, &b) {
+            return Ok(Numbers::Ints(*a, *b));
+        }
+        if let (Some(a), Some(b)) = (a.as_big(), b.as_big()) {
+            return Ok(Numbers::Bigs(a, b));
+        }
+        if let (Some(a), Some(b)) = (a.as_f64(), b.as_f64()) {
+            return Ok(Numbers::Floats(a, b));
+        }
+        return Err(RuntimeErrorKind::BadOperands {operator, left: a.type_name(), right: b.type_name()});
+    }
+
+    /// applies arithmetic operator. integer division truncates and fails on zero,
+    /// float one follows IEEE 754 (1.0/0 is inf). div and % round toward negative infinity,
+    /// int raised to negative power is float
+    fn arithmetic(&self, op:Arithmetic, numbers:Numbers) -> Result<Value, RuntimeErrorKind> {
+        match numbers {
+            Numbers::Ints(_, 0) if matches!(op, Arithmetic::Div | Arithmetic::FloorDiv | Arithmetic::Mod) => {
+                Err(RuntimeErrorKind::ZeroDivision)
+            }
+            Numbers::Ints(a, b) if op==Arithmetic::Pow && b<0 => {Ok(Value::Float((a as f64).powi(b)))}
+            Numbers::Ints(a, b) => {
+                let checked = match op {
+                    Arithmetic::Add => {a.checked_add(b)}
+                    Arithmetic::Sub => {a.checked_sub(b)}
+                    Arithmetic::Mult => {a.checked_mul(b)}
+                    Arithmetic::Div => {a.checked_div(b)}
+                    Arithmetic::FloorDiv => {floor_div(a, b)}
+                    Arithmetic::Mod => {Some(floor_mod(a, b))}
+                    Arithmetic::Pow => {a.checked_pow(b as u32)}
+                };
+                if let Some(result) = checked {
+                    return Ok(Value::Int(result));
+                }
+                //overflow, i32::MIN / -1 included
+                if self.options.integer_mode==IntegerMode::Big {
+                    return self.arithmetic(op, Numbers::Bigs(BigInt::from(a), BigInt::from(b)));
+                }
+                match self.options.overflow {
+                    OverflowPolicy::Trap => {Err(RuntimeErrorKind::IntegerOverflow)}
+                    OverflowPolicy::Wrap => {
+                        Ok(Value::Int(match op {
+                            Arithmetic::Add => {a.wrapping_add(b)}
+                            Arithmetic::Sub => {a.wrapping_sub(b)}
+                            Arithmetic::Mult => {a.wrapping_mul(b)}
+                            Arithmetic::Div | Arithmetic::FloorDiv => {a.wrapping_div(b)}
+                            Arithmetic::Mod => {floor_mod(a, b)}
+                            Arithmetic::Pow => {a.wrapping_pow(b as u32)}
+                        }))
+                    }
+                    OverflowPolicy::Saturate => {
+                        Ok(Value::Int(match op {
+                            Arithmetic::Add => {a.saturating_add(b)}
+                            Arithmetic::Sub => {a.saturating_sub(b)}
+                            Arithmetic::Mult => {a.saturating_mul(b)}
+                            Arithmetic::Div | Arithmetic::FloorDiv => {a.saturating_div(b)}
+                            Arithmetic::Mod => {floor_mod(a, b)}
+                            Arithmetic::Pow => {a.saturating_pow(b as u32)}
+                        }))
+                    }
+                }
+            }
+            Numbers::Bigs(a, b) if op==Arithmetic::Pow && b.is_negative() => {
+                Ok(Value::Float(a.to_f64().powf(b.to_f64())))
+            }
+            Numbers::Bigs(a, b) => {
+                let result = match op {
+                    Arithmetic::Add => {&a + &b}
+                    Arithmetic::Sub => {&a - &b}
+                    Arithmetic::Mult => {&a * &b}
+                    Arithmetic::Div => {a.checked_div(&b).ok_or(RuntimeErrorKind::ZeroDivision)?}
+                    Arithmetic::FloorDiv => {a.checked_divmod_floor(&b).ok_or(RuntimeErrorKind::ZeroDivision)?.0}
+                    Arithmetic::Mod => {a.checked_divmod_floor(&b).ok_or(RuntimeErrorKind::ZeroDivision)?.1}
+                    Arithmetic::Pow => {
+                        let exponent = b.to_i32().ok_or(RuntimeErrorKind::IntegerOverflow)?;
+                        if !a.is_zero() {
+                            //bits of a**exponent, at least
+                            self.check_allocation(((a.bits()-1)*exponent as u64+1)/8)?;
+                        }
+                        a.pow(exponent as u32)
+                    }
+                };
+                Ok(Value::from_big(result))
+            }
+            Numbers::Floats(a, b) => {
+                Ok(Value::Float(match op {
+                    Arithmetic::Add => {a+b}
+                    Arithmetic::Sub => {a-b}
+                    Arithmetic::Mult => {a*b}
+                    Arithmetic::Div => {a/b}
+                    Arithmetic::FloorDiv => {(a/b).floor()}
+                    Arithmetic::Mod => {
+                        let rem = a%b;
+                        if rem!=0.0 && (rem<0.0)!=(b<0.0) {rem+b} else {rem}
+                    }
+                    Arithmetic::Pow => {a.powf(b)}
+                }))
+            }
+        }
+    }
+
+    /// applies bitwise operator, only integers are accepted. shifts behave as multiplication
+    /// and floor division by power of two, so left shift may overflow
+    fn bitwise(&self, op:Bitwise, operator:&'static str, a:Value, b:Value) -> Result<Value, RuntimeErrorKind> {
+        let numbers = match VM::numbers(operator, a.clone(), b.clone())? {
+            Numbers::Floats(..) => {
+                return Err(RuntimeErrorKind::BadOperands {operator, left: a.type_name(), right: b.type_name()});
+            }
+            numbers => {numbers}
+        };
+        match numbers {
+            Numbers::Ints(_, b) if b<0 && matches!(op, Bitwise::Shl | Bitwise::Shr) => {Err(RuntimeErrorKind::NegativeShift)}
+            Numbers::Ints(a, b) => {
+                let checked = match op {
+                    Bitwise::And => {Some(a & b)}
+                    Bitwise::Or => {Some(a | b)}
+                    Bitwise::Xor => {Some(a ^ b)}
+                    Bitwise::Shl if a==0 => {Some(0)}
+                    Bitwise::Shl if b<32 => {i32::try_from((a as i64) << b).ok()}
+                    Bitwise::Shl => {None}
+                    Bitwise::Shr => {Some(a >> b.min(31))}
+                };
+                if let Some(result) = checked {
+                    return Ok(Value::Int(result));
+                }
+                //only left shift overflows
+                if self.options.integer_mode==IntegerMode::Big {
+                    let a = BigInt::from(a);
+                    self.check_allocation((a.bits()+b as u64)/8)?;
+                    return Ok(Value::from_big(&a * &BigInt::from(2).pow(b as u32)));
+                }
+                match self.options.overflow {
+                    OverflowPolicy::Trap => {Err(RuntimeErrorKind::IntegerOverflow)}
+                    OverflowPolicy::Wrap => {Ok(Value::Int(if b<32 {a.wrapping_shl(b as u32)} else {0}))}
+                    OverflowPolicy::Saturate => {Ok(Value::Int(if a>0 {i32::MAX} else {i32::MIN}))}
+                }
+            }
+            Numbers::Bigs(_, b) if b.is_negative() && matches!(op, Bitwise::Shl | Bitwise::Shr) => {Err(RuntimeErrorKind::NegativeShift)}
+            Numbers::Bigs(a, b) => {
+                let result = match op {
+                    Bitwise::And => {&a & &b}
+                    Bitwise::Or => {&a | &b}
+                    Bitwise::Xor => {&a ^ &b}
+                    Bitwise::Shl => {
+                        let shift = b.to_i32().ok_or(RuntimeErrorKind::IntegerOverflow)?;
+                        self.check_allocation((a.bits()+shift as u64)/8)?;
+                        &a * &BigInt::from(2).pow(shift as u32)
+                    }
+                    Bitwise::Shr => {
+                        match b.to_i32() {
+                            Some(shift) if (shift as u64)<a.bits() => {
+                                a.checked_divmod_floor(&BigInt::from(2).pow(shift as u32)).unwrap().0
+                            }
+                            _ => {BigInt::from(if a.is_negative() {-1} else {0})} //everything is shifted out
+                        }
+                    }
+                };
+                Ok(Value::from_big(result))
+            }
+            Numbers::Floats(..) => {unreachable!()}
+        }
+    }
+
+    /// pops operands of binary numeric operator
+    fn pop_numbers(&mut self, operator:&'static str) -> Result<Numbers, RuntimeErrorKind> {
+        let b = self.checked_stack_pop()?;
+        let a = self.checked_stack_pop()?;
+        return VM::numbers(operator, a, b);
+    }
+
+    /// position in list of given length that index refers to
+    fn list_position(index:&Value, length:usize) -> Result<usize, RuntimeErrorKind> {
+        match index {
+            Value::Int(i) if *i>=0 && (*i as usize)<length => {Ok(*i as usize)}
+            Value::Int(i) => {Err(RuntimeErrorKind::IndexOutOfBounds {index: i.to_string(), length})}
+            Value::BigInt(n) => {Err(RuntimeErrorKind::IndexOutOfBounds {index: n.to_string(), length})}
+            other => {Err(RuntimeErrorKind::TypeMismatch {expected: "int", found: other.type_name()})}
+        }
+    }
+
+    fn expect_bool(value:Value) -> Result<bool, RuntimeErrorKind> {
+        match value {
+            Value::Bool(b) => {Ok(b)}
+            other => {Err(RuntimeErrorKind::TypeMismatch {expected: "bool", found: other.type_name()})}
+        }
+    }
+
+    /// returns upvalue for stack slot, creating it if variable was not captured yet
+    fn capture_upvalue(&mut self, stack_idx:usize) -> Rc<RefCell<Upvalue>> {
+        for upvalue in &self.open_upvalues {
+            if let Upvalue::Open(idx) = *upvalue.borrow() {
+                if idx==stack_idx {return upvalue.clone();}
+            }
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(stack_idx)));
+        self.open_upvalues.push(upvalue.clone());
+        return upvalue;
+    }
+
+    /// moves values of stack slots starting from `from` into upvalues that point to them
+    fn close_upvalues(&mut self, from:usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let idx = match *upvalue.borrow() {
+                Upvalue::Open(idx) => {idx}
+                Upvalue::Closed(_) => {return false;}
+            };
+            if idx<from {return true;}
+            *upvalue.borrow_mut() = Upvalue::Closed(stack[idx].clone());
+            return false;
+        });
+    }
+
+    fn upvalue_of_frame(&self, idx:usize) -> Result<Rc<RefCell<Upvalue>>, RuntimeErrorKind> {
+        return self.frames.last()
+            .and_then(|frame| self.heap.closure(frame.closure).upvalues.get(idx))
+            .cloned()
+            .ok_or(RuntimeErrorKind::BadVariableIndex);
+    }
+
+    fn reset_variable_stack(&mut self){
+        self.close_upvalues(self.initial_stack_size);
+        self.stack.truncate(self.initial_stack_size);
+        self.frames.clear();
+        self.frame_base = self.initial_stack_size;
+    }
+
+    pub fn run(&mut self, code_chunk:&Chunk) -> Result<(), RuntimeError> {
+        return self.run_limited(code_chunk, &Limits::default());
+    }
+
+    /// runs chunk, stops with LimitExceeded error when code goes beyond limits
+    pub fn run_limited(&mut self, code_chunk:&Chunk, limits:&Limits) -> Result<(), RuntimeError> {
+        self.limits = *limits;
+        self.interrupt.store(false, Ordering::Relaxed);
+        if code_chunk.variable_size>0 { //add variable storage if needed
+            self.initial_stack_size+=code_chunk.variable_size;
+            self.stack.append(&mut vec![Value::Int(0); code_chunk.variable_size]);
+        }
+        self.frame_base = self.initial_stack_size;
+
+        let mut ip = 0;
+
+        let mut idx_register:usize = 0;
+
+        let mut status = Ok(());
+
+        let mut executed:u64 = 0;
+
+        //function of innermost frame, top level chunk is executed when there are no frames
+        let mut function:Option<Rc<Function>> = None;
+
+        loop {
+            let chunk = match &function {
+                Some(f) => {&f.chunk}
+                None => {code_chunk}
+            };
+            if ip>=chunk.program.len() {break;}
+
+            let current = ip;
+            ip+=1;
+            let depth = self.frames.len();
+            let result = self.check_budget(&mut executed)
+                .and_then(|_| self.execute(chunk, current, &mut ip, &mut idx_register))
+                .and_then(|_| self.check_memory());
+            if let Err(kind) = result {
+                //report location of failing instruction
+                status = Err(RuntimeError{
+                    kind,
+                    span: chunk.spans.get(current).copied(),
+                    opcode: Some(chunk.program[current])
+                });
+                break;
+            }
+            if self.frames.len()!=depth { //call or return happened
+                function = self.frames.last().map(|frame| frame.function.clone());
+            }
+        }
+
+        self.reset_variable_stack();
+        return status;
+    }
+
+    /// executes instruction at current. ip points to next instruction and is changed by jumps
+    fn execute(&mut self, code_chunk:&Chunk, current:usize, ip:&mut usize, idx_register:&mut usize) -> Result<(), RuntimeErrorKind> {
+        match code_chunk.program[current] {
+            OpCode::Add => {
+                let b = self.checked_stack_pop()?;
+                let a = self.checked_stack_pop()?;
+                let result = match (a, b) {
+                    (Value::Str(a), Value::Str(b)) => {
+                        self.check_allocation((a.len()+b.len()) as u64)?;
+                        let mut concatenated = String::with_capacity(a.len()+b.len());
+                        concatenated.push_str(&a);
+                        concatenated.push_str(&b);
+                        Value::Str(concatenated.into())
+                    }
+                    (a, b) => {
+                        let numbers = VM::numbers("+", a, b)?;
+                        self.arithmetic(Arithmetic::Add, numbers)?
+                    }
+                };
+                self.push_new(result);
+            }
+            OpCode::Sub => {
+                let numbers = self.pop_numbers("-")?;
+                let result = self.arithmetic(Arithmetic::Sub, numbers)?;
+                self.push_new(result);
+            }
+            OpCode::Mult => {
+                let numbers = self.pop_numbers("*")?;
+                let result = self.arithmetic(Arithmetic::Mult, numbers)?;
+                self.push_new(result);
+            }
+            OpCode::Div => {
+                let numbers = self.pop_numbers("/")?;
+                let result = self.arithmetic(Arithmetic::Div, numbers)?;
+                self.push_new(result);
+            }
+            OpCode::FloorDiv => {
+                let numbers = self.pop_numbers("div")?;
+                let result = self.arithmetic(Arithmetic::FloorDiv, numbers)?;
+                self.push_new(result);
+            }
+            OpCode::Mod => {
+                let numbers = self.pop_numbers("%")?;
+                let result = self.arithmetic(Arithmetic::Mod, numbers)?;
+                self.push_new(result);
+            }
+            OpCode::Pow => {
+                let numbers = self.pop_numbers("**")?;
+                let result = self.arithmetic(Arithmetic::Pow, numbers)?;
+                self.push_new(result);
+            }
+            OpCode::BitAnd | OpCode::BitOr | OpCode::BitXor | OpCode::Shl | OpCode::Shr => {
+                let (op, operator) = match code_chunk.program[current] {
+                    OpCode::BitAnd => {(Bitwise::And, "&")}
+                    OpCode::BitOr => {(Bitwise::Or, "|")}
+                    OpCode::BitXor => {(Bitwise::Xor, "^")}
+                    OpCode::Shl => {(Bitwise::Shl, "<<")}
+                    _ => {(Bitwise::Shr, ">>")}
+                };
+                let b = self.checked_stack_pop()?;
+                let a = self.checked_stack_pop()?;
+                let result = self.bitwise(op, operator, a, b)?;
+                self.push_new(result);
+            }
+            OpCode::Negate => {
+                let result = match self.checked_stack_pop()? {
+                    Value::Float(n) => {Value::Float(-n)}
+                    //same as 0 - n, so overflow of -i32::MIN follows policy
+                    value @ (Value::Int(_) | Value::BigInt(_)) => {
+                        let numbers = VM::numbers("-", Value::Int(0), value)?;
+                        self.arithmetic(Arithmetic::Sub, numbers)?
+                    }
+                    other => {return Err(RuntimeErrorKind::TypeMismatch {expected: "number", found: other.type_name()});}
+                };
+                self.push_new(result);
+            }
+            OpCode::Positive => {
+                let value = self.checked_stack_pop()?;
+                if value.as_f64().is_none() {
+                    return Err(RuntimeErrorKind::TypeMismatch {expected: "number", found: value.type_name()});
+                }
+                self.stack.push(value);
+            }
+            OpCode::BitNot => {
+                let result = match self.checked_stack_pop()? {
+                    Value::Int(i) => {Value::Int(!i)}
+                    Value::BigInt(n) => {Value::from_big(&(-n.as_ref()) - &BigInt::from(1))}
+                    other => {return Err(RuntimeErrorKind::TypeMismatch {expected: "int", found: other.type_name()});}
+                };
+                self.push_new(result);
+            }
+            OpCode::Equal | OpCode::NotEqual => {
+                let b = self.checked_stack_pop()?;
+                let a = self.checked_stack_pop()?;
+                let equal = self.heap.values_equal(&a, &b);
+                self.stack.push(Value::Bool(if let OpCode::Equal = code_chunk.program[current] {equal} else {!equal}));
+            }
+            OpCode::Less => {
+                let result = match self.pop_numbers("<")? {
+                    Numbers::Ints(a, b) => {a<b}
+                    Numbers::Bigs(a, b) => {a<b}
+                    Numbers::Floats(a, b) => {a<b}
+                };
+                self.stack.push(Value::Bool(result));
+            }
+            OpCode::LessEqual => {
+                let result = match self.pop_numbers("<=")? {
+                    Numbers::Ints(a, b) => {a<=b}
+                    Numbers::Bigs(a, b) => {a<=b}
+                    Numbers::Floats(a, b) => {a<=b}
+                };
+                self.stack.push(Value::Bool(result));
+            }
+            OpCode::Greater => {
+                let result = match self.pop_numbers(">")? {
+                    Numbers::Ints(a, b) => {a>b}
+                    Numbers::Bigs(a, b) => {a>b}
+                    Numbers::Floats(a, b) => {a>b}
+                };
+                self.stack.push(Value::Bool(result));
+            }
+            OpCode::GreaterEqual => {
+                let result = match self.pop_numbers(">=")? {
+                    Numbers::Ints(a, b) => {a>=b}
+                    Numbers::Bigs(a, b) => {a>=b}
+                    Numbers::Floats(a, b) => {a>=b}
+                };
+                self.stack.push(Value::Bool(result));
+            }
+            OpCode::Not => {
+                let value = VM::expect_bool(self.checked_stack_pop()?)?;
+                self.stack.push(Value::Bool(!value));
+            }
+            OpCode::True => {self.stack.push(Value::Bool(true));}
+            OpCode::False => {self.stack.push(Value::Bool(false));}
+            OpCode::Store(i) => {
+                let value = self.checked_stack_pop()?;
+                let idx = (*idx_register<<8) + i as usize;
+                *idx_register = 0;
+                if idx>=self.initial_stack_size {return Err(RuntimeErrorKind::BadVariableIndex);}
+
+                self.stack[idx] = value;
+            }
+            OpCode::LoadVar(i) => {
+                let idx = (*idx_register<<8) + i as usize;
+                *idx_register = 0;
+                if idx>=self.initial_stack_size {return Err(RuntimeErrorKind::BadVariableIndex);}
+
+                self.stack.push(self.stack[idx].clone());
+            }
+            OpCode::StoreLocal(i) => {
+                let value = self.checked_stack_pop()?;
+                let idx = self.frame_base + (*idx_register<<8) + i as usize;
+                *idx_register = 0;
+                if idx>=self.stack.len() {return Err(RuntimeErrorKind::BadVariableIndex);}
+
+                self.stack[idx] = value;
+            }
+            OpCode::LoadLocal(i) => {
+                let idx = self.frame_base + (*idx_register<<8) + i as usize;
+                *idx_register = 0;
+                if idx>=self.stack.len() {return Err(RuntimeErrorKind::BadVariableIndex);}
+
+                self.stack.push(self.stack[idx].clone());
+            }
+            OpCode::Pop => {
+                self.checked_stack_pop()?;
+            }
+            OpCode::Dup => {
+                let value = self.checked_stack_peek()?;
+                self.stack.push(value);
+            }
+            OpCode::DupTwo => {
+                if self.stack.len() < self.frame_base+2 {return Err(RuntimeErrorKind::StackUnderflow);}
+                self.stack.extend_from_within(self.stack.len()-2..);
+            }
+            OpCode::BuildList(i) => {
+                let count = (*idx_register<<8) + i as usize;
+                *idx_register = 0;
+                if self.stack.len() < self.frame_base+count {return Err(RuntimeErrorKind::StackUnderflow);}
+
+                self.collect_if_needed();
+                let items = self.stack.split_off(self.stack.len()-count);
+                let list = self.heap.alloc(Object::List(items));
+                self.stack.push(Value::List(list));
+            }
+            OpCode::BuildMap(i) => {
+                let count = (*idx_register<<8) + i as usize;
+                *idx_register = 0;
+                if self.stack.len() < self.frame_base+count*2 {return Err(RuntimeErrorKind::StackUnderflow);}
+
+                self.collect_if_needed();
+                let items = self.stack.split_off(self.stack.len()-count*2);
+                let mut map = Map::default();
+                for pair in items.chunks(2) {
+                    map.insert(MapKey::from_value(&pair[0])?, pair[1].clone());
+                }
+                let map = self.heap.alloc(Object::Map(map));
+                self.stack.push(Value::Map(map));
+            }
+            OpCode::Index => {
+                let index = self.checked_stack_pop()?;
+                let result = match self.checked_stack_pop()? {
+                    Value::List(list) => {
+                        let list = self.heap.list(list);
+                        list[VM::list_position(&index, list.len())?].clone()
+                    }
+                    Value::Map(map) => {
+                        match self.heap.map(map).get(&MapKey::from_value(&index)?) {
+                            Some(value) => {value.clone()}
+                            None => {return Err(RuntimeErrorKind::KeyNotFound(self.heap.repr(&index)));}
+                        }
+                    }
+                    other => {return Err(RuntimeErrorKind::NotIndexable(other.type_name()));}
+                };
+                self.stack.push(result);
+            }
+            OpCode::SetIndex => {
+                let value = self.checked_stack_pop()?;
+                let index = self.checked_stack_pop()?;
+                match self.checked_stack_pop()? {
+                    Value::List(list) => {
+                        let list = self.heap.list_mut(list);
+                        let position = VM::list_position(&index, list.len())?;
+                        list[position] = value.clone();
+                    }
+                    Value::Map(map) => {
+                        if self.heap.map_mut(map).insert(MapKey::from_value(&index)?, value.clone()) {
+                            self.heap.grow(MAP_ENTRY_SIZE);
+                        }
+                    }
+                    other => {return Err(RuntimeErrorKind::NotIndexable(other.type_name()));}
+                }
+                self.stack.push(value);
+            }
+            OpCode::Len => {
+                let length = match self.checked_stack_pop()? {
+                    Value::List(list) => {self.heap.list(list).len()}
+                    Value::Map(map) => {self.heap.map(map).len()}
+                    Value::Str(s) => {s.chars().count()}
+                    other => {return Err(RuntimeErrorKind::TypeMismatch {expected: "list, map or string", found: other.type_name()});}
+                };
+                self.stack.push(Value::Int(i32::try_from(length).map_err(|_| RuntimeErrorKind::IntegerOverflow)?));
+            }
+            OpCode::Contains => {
+                let collection = self.checked_stack_pop()?;
+                let item = self.checked_stack_pop()?;
+                let result = match (&item, &collection) {
+                    (_, Value::Map(map)) => {self.heap.map(*map).contains_key(&MapKey::from_value(&item)?)}
+                    (_, Value::List(list)) => {self.heap.list(*list).iter().any(|x| self.heap.values_equal(x, &item))}
+                    (Value::Str(part), Value::Str(s)) => {s.contains(part.as_ref())}
+                    _ => {
+                        return Err(RuntimeErrorKind::BadOperands {operator: "in", left: item.type_name(), right: collection.type_name()});
+                    }
+                };
+                self.stack.push(Value::Bool(result));
+            }
+            OpCode::IterItem => {
+                let position = self.checked_stack_pop()?;
+                let item = match self.checked_stack_pop()? {
+                    Value::List(list) => {
+                        let list = self.heap.list(list);
+                        list[VM::list_position(&position, list.len())?].clone()
+                    }
+                    Value::Map(map) => {
+                        let map = self.heap.map(map);
+                        map.key_at(VM::list_position(&position, map.len())?).to_value()
+                    }
+                    Value::Str(s) => {
+                        let length = s.chars().count();
+                        let c = s.chars().nth(VM::list_position(&position, length)?).unwrap();
+                        let item = Value::Str(c.to_string().into());
+                        self.heap.account_value(&item);
+                        item
+                    }
+                    other => {return Err(RuntimeErrorKind::TypeMismatch {expected: "list, map or string", found: other.type_name()});}
+                };
+                self.stack.push(item);
+            }
+            OpCode::Jump(i) => {
+                *ip = (*idx_register<<8) + i as usize;
+                *idx_register = 0;
+            }
+            OpCode::JumpIfFalse(i) | OpCode::JumpIfTrue(i) => {
+                let target = (*idx_register<<8) + i as usize;
+                *idx_register = 0;
+                let condition = VM::expect_bool(self.checked_stack_peek()?)?;
+                let jump_on = matches!(code_chunk.program[current], OpCode::JumpIfTrue(_));
+                if condition==jump_on {
+                    *ip = target;
+                }
+            }
+            OpCode::Print => {
+                let value = self.checked_stack_pop()?;
+                writeln!(self.out, "{}", self.heap.display(&value))
+                    .map_err(|e| RuntimeErrorKind::OutputError(e.to_string()))?;
+            }
+            OpCode::Extend(i) => {
+                *idx_register = (*idx_register<<8) + i as usize;
+            }
+            OpCode::LoadConst(i) => {
+                let idx = (*idx_register<<8) + i as usize;
+                *idx_register = 0;
+                let value = match &code_chunk.constant_pool[idx] {
+                    Constant::Int(i) => {Value::Int(*i)}
+                    Constant::BigInt(n) => {
+                        //lexer rejects such literals in fixed mode, chunk was compiled for big integer VM
+                        if self.options.integer_mode!=IntegerMode::Big {
+                            return Err(RuntimeErrorKind::IntegerOverflow);
+                        }
+                        Value::BigInt(n.clone())
+                    }
+                    Constant::Float(n) => {Value::Float(*n)}
+                    Constant::Str(s) => {Value::Str(s.clone())}
+                    Constant::Function(_) => {return Err(RuntimeErrorKind::BadVariableIndex);} //needs Closure
+                };
+                self.stack.push(value);
+            }
+            OpCode::Closure(i) => {
+                let idx = (*idx_register<<8) + i as usize;
+                *idx_register = 0;
+                self.collect_if_needed();
+                let function = match &code_chunk.constant_pool[idx] {
+                    Constant::Function(function) => {function.clone()}
+                    _ => {return Err(RuntimeErrorKind::BadVariableIndex);}
+                };
+                let mut upvalues = Vec::with_capacity(function.captures.len());
+                for capture in &function.captures {
+                    if capture.is_local {
+                        upvalues.push(self.capture_upvalue(self.frame_base+capture.index));
+                    }else{
+                        upvalues.push(self.upvalue_of_frame(capture.index)?);
+                    }
+                }
+                let closure = self.heap.alloc(Object::Closure(Closure{function, upvalues}));
+                self.stack.push(Value::Closure(closure));
+            }
+            OpCode::StoreUpvalue(i) => {
+                let value = self.checked_stack_pop()?;
+                let upvalue = self.upvalue_of_frame((*idx_register<<8) + i as usize)?;
+                *idx_register = 0;
+                let mut upvalue = upvalue.borrow_mut();
+                match &mut *upvalue {
+                    Upvalue::Open(idx) => {self.stack[*idx] = value;}
+                    Upvalue::Closed(closed) => {*closed = value;}
+                }
+            }
+            OpCode::LoadUpvalue(i) => {
+                let upvalue = self.upvalue_of_frame((*idx_register<<8) + i as usize)?;
+                *idx_register = 0;
+                let value = match &*upvalue.borrow() {
+                    Upvalue::Open(idx) => {self.stack[*idx].clone()}
+                    Upvalue::Closed(value) => {value.clone()}
+                };
+                self.stack.push(value);
+            }
+            OpCode::CloseUpvalue => {
+                self.checked_stack_peek()?;
+                self.close_upvalues(self.stack.len()-1);
+                self.stack.pop();
+            }
+            OpCode::Call(i) => {
+                let argc = (*idx_register<<8) + i as usize;
+                *idx_register = 0;
+                if self.stack.len() < self.frame_base+argc+1 {return Err(RuntimeErrorKind::StackUnderflow);}
+
+                let callee_idx = self.stack.len()-argc-1;
+                let closure = match &self.stack[callee_idx] {
+                    Value::Closure(closure) => {*closure}
+                    other => {return Err(RuntimeErrorKind::NotCallable(other.type_name()));}
+                };
+                let function = self.heap.closure(closure).function.clone();
+                if function.arity!=argc {
+                    return Err(RuntimeErrorKind::ArityMismatch {expected: function.arity, found: argc});
+                }
+                if self.frames.len()>=MAX_FRAMES {return Err(RuntimeErrorKind::StackOverflow);}
+
+                self.frame_base = callee_idx+1;
+                self.frames.push(CallFrame{closure, function, return_ip:*ip, base:self.frame_base});
+                *ip = 0;
+            }
+            OpCode::Return => {
+                let result = self.checked_stack_pop()?;
+                let frame = self.frames.pop().ok_or(RuntimeErrorKind::StackUnderflow)?;
+                //drop locals, arguments and callee. locals that are still captured survive in upvalues
+                self.close_upvalues(frame.base);
+                self.stack.truncate(frame.base-1);
+                self.stack.push(result);
+                self.frame_base = self.frames.last().map(|caller| caller.base).unwrap_or(self.initial_stack_size);
+                *ip = frame.return_ip;
+            }
+            OpCode::CallNative(i) => {
+                let idx = (*idx_register<<8) + i as usize;
+                *idx_register = 0;
+                let native = self.natives.get(idx).ok_or(RuntimeErrorKind::BadVariableIndex)?;
+                if self.stack.len() < self.frame_base+native.arity {return Err(RuntimeErrorKind::StackUnderflow);}
+
+                let args_start = self.stack.len()-native.arity;
+                for arg in &self.stack[args_start..] {
+                    arg.check_host_value()?;
+                }
+                let result = (native.function)(&self.stack[args_start..])
+                    .map_err(|message| RuntimeErrorKind::NativeError {name: native.name.clone(), message})?;
+                result.check_host_value()?;
+                self.stack.truncate(args_start);
+                self.push_new(result);
+            }
+        }
+        return Ok(());
+    }
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        VM::new()
+    }
+}